@@ -4,7 +4,9 @@ use std::path::PathBuf;
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let out_dir = PathBuf::from(env::var("OUT_DIR")?);
 
-    tonic_build::compile_protos("../shared/proto/auditor.proto")?;
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("auditor_descriptor.bin"))
+        .compile(&["../shared/proto/auditor.proto"], &["../shared/proto"])?;
 
     let protos = &[
         "../shared/proto/trillian/trillian.proto",
@@ -0,0 +1,34 @@
+-- Schema for the embedded SQLite backend, equivalent to the Postgres
+-- migrations up to merkle_leaves. Timestamps are microseconds since the
+-- epoch; contexts are JSON text.
+CREATE TABLE IF NOT EXISTS receipts (
+    tenant_id TEXT NOT NULL DEFAULT 'default',
+    leaf_hash BLOB NOT NULL,
+    log_id INTEGER,
+    proxy_id TEXT,
+    leaf_index INTEGER,
+    root_hash BLOB,
+    context TEXT NOT NULL,
+    receipt_jwt TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'integrated',
+    created_at INTEGER NOT NULL,
+    integrated_at INTEGER,
+    PRIMARY KEY (tenant_id, leaf_hash)
+);
+
+CREATE INDEX IF NOT EXISTS idx_receipts_pending ON receipts(created_at) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS idx_receipts_tenant_page ON receipts(tenant_id, created_at, leaf_hash);
+CREATE INDEX IF NOT EXISTS idx_receipts_proxy_id ON receipts(tenant_id, proxy_id);
+
+CREATE TABLE IF NOT EXISTS tenant_logs (
+    tenant_id TEXT PRIMARY KEY,
+    log_id INTEGER NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS merkle_leaves (
+    log_id INTEGER NOT NULL,
+    leaf_index INTEGER NOT NULL,
+    leaf_value BLOB NOT NULL,
+    PRIMARY KEY (log_id, leaf_index)
+);
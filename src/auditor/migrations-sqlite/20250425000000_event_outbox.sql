@@ -0,0 +1,13 @@
+-- See the Postgres migration of the same name.
+CREATE TABLE IF NOT EXISTS event_outbox (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    tenant_id TEXT NOT NULL,
+    log_id INTEGER NOT NULL,
+    leaf_hash BLOB NOT NULL,
+    leaf_index INTEGER NOT NULL,
+    receipt_jwt TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    delivered_at INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS idx_event_outbox_undelivered ON event_outbox(log_id, leaf_index) WHERE delivered_at IS NULL;
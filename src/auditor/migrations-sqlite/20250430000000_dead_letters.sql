@@ -0,0 +1,15 @@
+-- See the Postgres migration of the same name.
+CREATE TABLE IF NOT EXISTS dead_letters (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    tenant_id TEXT NOT NULL,
+    proxy_id TEXT NOT NULL,
+    leaf_hash BLOB NOT NULL,
+    metadata BLOB NOT NULL,
+    timestamp_ns INTEGER NOT NULL,
+    stage TEXT NOT NULL,
+    error TEXT NOT NULL,
+    retryable INTEGER NOT NULL,
+    attempts INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    replayed_at INTEGER
+);
@@ -0,0 +1,12 @@
+-- See the Postgres migration of the same name.
+CREATE TABLE IF NOT EXISTS revocations (
+    tenant_id TEXT NOT NULL,
+    leaf_hash BLOB NOT NULL,
+    log_id INTEGER NOT NULL,
+    reason TEXT NOT NULL,
+    actor TEXT NOT NULL,
+    statement TEXT NOT NULL,
+    revocation_leaf_hash BLOB NOT NULL,
+    revoked_at INTEGER NOT NULL,
+    PRIMARY KEY (tenant_id, leaf_hash)
+);
@@ -0,0 +1,9 @@
+-- See the Postgres migration of the same name.
+CREATE TABLE IF NOT EXISTS event_deliveries (
+    event_id INTEGER NOT NULL REFERENCES event_outbox(id) ON DELETE CASCADE,
+    sink TEXT NOT NULL,
+    delivered_at INTEGER NOT NULL,
+    PRIMARY KEY (event_id, sink)
+);
+
+CREATE INDEX IF NOT EXISTS idx_event_outbox_delivered ON event_outbox(delivered_at) WHERE delivered_at IS NOT NULL;
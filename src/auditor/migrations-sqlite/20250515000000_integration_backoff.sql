@@ -0,0 +1,6 @@
+-- See the Postgres migration of the same name.
+ALTER TABLE receipts ADD COLUMN integrate_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE receipts ADD COLUMN next_integrate_at INTEGER;
+
+DROP INDEX IF EXISTS idx_receipts_pending;
+CREATE INDEX IF NOT EXISTS idx_receipts_pending ON receipts(COALESCE(next_integrate_at, created_at)) WHERE status = 'pending';
@@ -0,0 +1,8 @@
+-- Receipts are stored as a signed promise first and upgraded once the leaf
+-- is integrated into the log, so index and root are unknown until then.
+ALTER TABLE receipts ALTER COLUMN leaf_index DROP NOT NULL;
+ALTER TABLE receipts ALTER COLUMN root_hash DROP NOT NULL;
+ALTER TABLE receipts ADD COLUMN IF NOT EXISTS status TEXT NOT NULL DEFAULT 'integrated';
+ALTER TABLE receipts ADD COLUMN IF NOT EXISTS integrated_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS idx_receipts_pending ON receipts(created_at) WHERE status = 'pending';
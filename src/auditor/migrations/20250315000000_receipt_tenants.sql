@@ -0,0 +1,7 @@
+-- Receipts belong to the tenant that submitted them; the same hash may be
+-- submitted independently by different tenants.
+ALTER TABLE receipts ADD COLUMN IF NOT EXISTS tenant_id TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE receipts DROP CONSTRAINT IF EXISTS receipts_pkey;
+ALTER TABLE receipts ADD PRIMARY KEY (tenant_id, leaf_hash);
+
+CREATE INDEX IF NOT EXISTS idx_receipts_leaf_hash ON receipts(leaf_hash);
@@ -0,0 +1,10 @@
+-- Each tenant may have its own Trillian tree; receipts record the tree they
+-- were issued against (NULL for receipts from before per-tenant logs, which
+-- all live in the default tree).
+CREATE TABLE IF NOT EXISTS tenant_logs (
+    tenant_id TEXT PRIMARY KEY,
+    log_id BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE receipts ADD COLUMN IF NOT EXISTS log_id BIGINT;
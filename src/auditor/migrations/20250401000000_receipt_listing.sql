@@ -0,0 +1,8 @@
+-- Support for ListReceipts: filtering by proxy, model and context fields,
+-- and keyset pagination over (created_at, leaf_hash) within a tenant.
+ALTER TABLE receipts ADD COLUMN IF NOT EXISTS proxy_id TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_receipts_tenant_page ON receipts(tenant_id, created_at, leaf_hash);
+CREATE INDEX IF NOT EXISTS idx_receipts_proxy_id ON receipts(tenant_id, proxy_id);
+CREATE INDEX IF NOT EXISTS idx_receipts_model_id ON receipts((context->'request_headers'->>'x-model-id'));
+CREATE INDEX IF NOT EXISTS idx_receipts_context ON receipts USING GIN (context jsonb_path_ops);
@@ -0,0 +1,8 @@
+-- Leaves of the embedded Merkle log, when it is configured to keep its
+-- trees in Postgres.
+CREATE TABLE IF NOT EXISTS merkle_leaves (
+    log_id BIGINT NOT NULL,
+    leaf_index BIGINT NOT NULL,
+    leaf_value BYTEA NOT NULL,
+    PRIMARY KEY (log_id, leaf_index)
+);
@@ -0,0 +1,15 @@
+-- Events for integrated receipts, written in the same transaction as the
+-- upgrade and published by the outbox relay, so a sink outage delays
+-- announcements instead of losing them.
+CREATE TABLE IF NOT EXISTS event_outbox (
+    id BIGSERIAL PRIMARY KEY,
+    tenant_id TEXT NOT NULL,
+    log_id BIGINT NOT NULL,
+    leaf_hash BYTEA NOT NULL,
+    leaf_index BIGINT NOT NULL,
+    receipt_jwt TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    delivered_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_event_outbox_undelivered ON event_outbox(log_id, leaf_index) WHERE delivered_at IS NULL;
@@ -0,0 +1,19 @@
+-- Submissions the batch worker gave up on, after a permanent error or once
+-- retries ran out, kept with the original submission for inspection and
+-- replay through the AuditorAdmin service.
+CREATE TABLE IF NOT EXISTS dead_letters (
+    id BIGSERIAL PRIMARY KEY,
+    tenant_id TEXT NOT NULL,
+    proxy_id TEXT NOT NULL,
+    leaf_hash BYTEA NOT NULL,
+    metadata BYTEA NOT NULL,
+    timestamp_ns BIGINT NOT NULL,
+    stage TEXT NOT NULL,
+    error TEXT NOT NULL,
+    retryable BOOLEAN NOT NULL,
+    attempts INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    replayed_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_dead_letters_unreplayed ON dead_letters(id) WHERE replayed_at IS NULL;
@@ -0,0 +1,13 @@
+-- Withdrawn receipts. The signed statement is also appended to the log;
+-- revocation_leaf_hash is the leaf it was logged as.
+CREATE TABLE IF NOT EXISTS revocations (
+    tenant_id TEXT NOT NULL,
+    leaf_hash BYTEA NOT NULL,
+    log_id BIGINT NOT NULL,
+    reason TEXT NOT NULL,
+    actor TEXT NOT NULL,
+    statement TEXT NOT NULL,
+    revocation_leaf_hash BYTEA NOT NULL,
+    revoked_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (tenant_id, leaf_hash)
+);
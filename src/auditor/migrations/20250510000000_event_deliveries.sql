@@ -0,0 +1,12 @@
+-- Which sinks have published each outbox event. An event is marked
+-- delivered once every configured sink has published it, so a failing sink
+-- is retried on its own without resending to the others.
+CREATE TABLE IF NOT EXISTS event_deliveries (
+    event_id BIGINT NOT NULL REFERENCES event_outbox(id) ON DELETE CASCADE,
+    sink TEXT NOT NULL,
+    delivered_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (event_id, sink)
+);
+
+-- Delivered events are pruned once past the outbox retention
+CREATE INDEX IF NOT EXISTS idx_event_outbox_delivered ON event_outbox(delivered_at) WHERE delivered_at IS NOT NULL;
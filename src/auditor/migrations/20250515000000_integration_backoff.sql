@@ -0,0 +1,8 @@
+-- Pending receipts whose leaves are not in the log yet are checked again
+-- after a growing delay, so leaves that never integrate cannot hold up the
+-- receipts submitted after them.
+ALTER TABLE receipts ADD COLUMN IF NOT EXISTS integrate_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE receipts ADD COLUMN IF NOT EXISTS next_integrate_at TIMESTAMPTZ;
+
+DROP INDEX IF EXISTS idx_receipts_pending;
+CREATE INDEX IF NOT EXISTS idx_receipts_pending ON receipts((COALESCE(next_integrate_at, created_at))) WHERE status = 'pending';
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+use tonic::{Request, Response, Status};
+use tracing::info;
+use crate::auditor::{
+    auditor_admin_server::AuditorAdmin, DeadLetter as DeadLetterMessage, HashSubmission,
+    ListDeadLettersRequest, ListDeadLettersResponse, ReceiptResponse, ReplayDeadLetterRequest,
+};
+use crate::auth;
+use crate::server::AuditorService;
+use crate::storage::{DeadLetterRecord, Storage};
+
+const DEFAULT_PAGE_SIZE: u32 = 100;
+const MAX_PAGE_SIZE: u32 = 1000;
+
+/// Operator RPCs for submissions the batch worker gave up on. Every call
+/// requires an admin key and may act on any tenant's submissions.
+pub struct AdminService {
+    storage: Arc<dyn Storage>,
+    auditor: AuditorService,
+}
+
+impl AdminService {
+    pub fn new(storage: Arc<dyn Storage>, auditor: AuditorService) -> Self {
+        Self { storage, auditor }
+    }
+}
+
+#[tonic::async_trait]
+impl AuditorAdmin for AdminService {
+    async fn list_dead_letters(
+        &self,
+        request: Request<ListDeadLettersRequest>,
+    ) -> Result<Response<ListDeadLettersResponse>, Status> {
+        auth::require_admin(&request)?;
+        let req = request.into_inner();
+        let page_size = match req.page_size {
+            0 => DEFAULT_PAGE_SIZE,
+            n => n.min(MAX_PAGE_SIZE),
+        };
+        let tenant_id = Some(req.tenant_id.as_str()).filter(|tenant_id| !tenant_id.is_empty());
+        // One extra row tells whether another page follows
+        let mut records = self.storage
+            .dead_letters(tenant_id, req.include_replayed, req.after_id, page_size as i64 + 1)
+            .await
+            .map_err(|e| Status::internal(format!("Storage error: {}", e)))?;
+        let next_after_id = if records.len() > page_size as usize {
+            records.truncate(page_size as usize);
+            records.last().map_or(0, |record| record.id)
+        } else {
+            0
+        };
+        Ok(Response::new(ListDeadLettersResponse {
+            dead_letters: records.iter().map(dead_letter_message).collect(),
+            next_after_id,
+        }))
+    }
+
+    async fn replay_dead_letter(
+        &self,
+        request: Request<ReplayDeadLetterRequest>,
+    ) -> Result<Response<ReceiptResponse>, Status> {
+        auth::require_admin(&request)?;
+        let id = request.into_inner().id;
+        let record = self.storage.get_dead_letter(id).await
+            .map_err(|e| Status::internal(format!("Storage error: {}", e)))?
+            .ok_or_else(|| Status::not_found(format!("No dead letter {}", id)))?;
+        let letter = record.letter;
+        let submission = HashSubmission {
+            hash: letter.leaf_hash,
+            metadata: letter.metadata,
+            proxy_id: letter.proxy_id,
+            timestamp_ns: letter.timestamp_ns as u64,
+            trace_context: Default::default(),
+        };
+        // Submissions are idempotent, so replaying one that has since been
+        // logged just returns its receipt
+        let response = self.auditor.enqueue(letter.tenant_id, submission).await?;
+        if response.status.is_none() {
+            self.storage.mark_replayed(id).await
+                .map_err(|e| Status::internal(format!("Storage error: {}", e)))?;
+            info!("Dead letter {} replayed", id);
+        }
+        Ok(Response::new(response))
+    }
+}
+
+fn dead_letter_message(record: &DeadLetterRecord) -> DeadLetterMessage {
+    let letter = &record.letter;
+    DeadLetterMessage {
+        id: record.id,
+        tenant_id: letter.tenant_id.clone(),
+        proxy_id: letter.proxy_id.clone(),
+        leaf_hash: letter.leaf_hash.clone(),
+        metadata: letter.metadata.clone(),
+        timestamp_ns: letter.timestamp_ns as u64,
+        stage: letter.stage.clone(),
+        error: letter.error.clone(),
+        retryable: letter.retryable,
+        attempts: letter.attempts as u32,
+        created_at_ns: unix_nanos(record.created_at),
+        replayed_at_ns: record.replayed_at.map_or(0, unix_nanos),
+    }
+}
+
+fn unix_nanos(time: chrono::DateTime<chrono::Utc>) -> u64 {
+    time.timestamp_nanos_opt().unwrap_or(0) as u64
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use tonic::metadata::MetadataMap;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+use crate::config::AuthConfig;
+
+/// Tenant that owns receipts when API keys are not configured.
+pub const DEFAULT_TENANT: &str = "default";
+
+/// The authenticated tenant of a request, attached by [`ApiKeyAuth`].
+#[derive(Debug, Clone)]
+pub struct Tenant(pub String);
+
+/// Attached by [`ApiKeyAuth`] to requests made with an admin key.
+#[derive(Debug, Clone, Copy)]
+pub struct Admin;
+
+struct KeyGrant {
+    tenant_id: String,
+    admin: bool,
+}
+
+/// Interceptor resolving the API key a caller presents (as
+/// `authorization: Bearer <key>` or `x-api-key`) to its tenant. Only SHA-256
+/// digests of the keys are held in config. With authentication disabled,
+/// every caller is the default tenant, and nobody is an admin.
+#[derive(Clone)]
+pub struct ApiKeyAuth {
+    // Key digest -> grant; empty when authentication is disabled
+    keys: Arc<HashMap<Vec<u8>, KeyGrant>>,
+}
+
+impl ApiKeyAuth {
+    pub fn new(cfg: &AuthConfig) -> Result<Self> {
+        let mut keys = HashMap::new();
+        for key in &cfg.api_keys {
+            let digest = hex::decode(&key.key_sha256)
+                .map_err(|e| anyhow!("Invalid key_sha256 for tenant {}: {}", key.tenant_id, e))?;
+            keys.insert(digest, KeyGrant {
+                tenant_id: key.tenant_id.clone(),
+                admin: key.admin,
+            });
+        }
+        Ok(Self { keys: Arc::new(keys) })
+    }
+
+    /// Whether any key may call the admin service.
+    pub fn has_admin(&self) -> bool {
+        self.keys.values().any(|grant| grant.admin)
+    }
+
+    pub fn authenticate(&self, metadata: &MetadataMap) -> Result<Tenant, Status> {
+        self.resolve(metadata).map(|(tenant, _)| tenant)
+    }
+
+    /// The caller's tenant and whether its key is an admin key.
+    fn resolve(&self, metadata: &MetadataMap) -> Result<(Tenant, bool), Status> {
+        if self.keys.is_empty() {
+            return Ok((Tenant(DEFAULT_TENANT.to_string()), false));
+        }
+        let key = presented_key(metadata)
+            .ok_or_else(|| Status::unauthenticated("Missing API key"))?;
+        let digest = Sha256::digest(key.as_bytes()).to_vec();
+        self.keys.get(&digest)
+            .map(|grant| (Tenant(grant.tenant_id.clone()), grant.admin))
+            .ok_or_else(|| Status::unauthenticated("Invalid API key"))
+    }
+}
+
+impl Interceptor for ApiKeyAuth {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let (tenant, admin) = self.resolve(request.metadata())?;
+        request.extensions_mut().insert(tenant);
+        if admin {
+            request.extensions_mut().insert(Admin);
+        }
+        Ok(request)
+    }
+}
+
+fn presented_key(metadata: &MetadataMap) -> Option<&str> {
+    if let Some(bearer) = metadata.get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        return Some(bearer.trim());
+    }
+    metadata.get("x-api-key").and_then(|value| value.to_str().ok())
+}
+
+/// The tenant the interceptor authenticated for this request.
+pub fn tenant_of<T>(request: &Request<T>) -> Result<String, Status> {
+    request.extensions()
+        .get::<Tenant>()
+        .map(|tenant| tenant.0.clone())
+        .ok_or_else(|| Status::unauthenticated("Request was not authenticated"))
+}
+
+/// Fails unless the request was made with an admin key.
+pub fn require_admin<T>(request: &Request<T>) -> Result<(), Status> {
+    request.extensions()
+        .get::<Admin>()
+        .map(|_| ())
+        .ok_or_else(|| Status::permission_denied("Admin API key required"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ApiKeyConfig;
+
+    #[test]
+    fn test_admin_requires_an_admin_key() {
+        let open = ApiKeyAuth::new(&AuthConfig::default()).unwrap();
+        assert!(!open.has_admin());
+        let (tenant, admin) = open.resolve(&MetadataMap::new()).unwrap();
+        assert_eq!(tenant.0, DEFAULT_TENANT);
+        assert!(!admin);
+
+        let key = |tenant_id: &str, key: &str, admin| ApiKeyConfig {
+            tenant_id: tenant_id.to_string(),
+            key_sha256: hex::encode(Sha256::digest(key.as_bytes())),
+            admin,
+        };
+        let auth = ApiKeyAuth::new(&AuthConfig { api_keys: vec![key("acme", "user-key", false), key("ops", "admin-key", true)] }).unwrap();
+        assert!(auth.has_admin());
+        let resolve = |key: &str| {
+            let mut metadata = MetadataMap::new();
+            metadata.insert("x-api-key", key.parse().unwrap());
+            auth.resolve(&metadata).map(|(tenant, admin)| (tenant.0, admin))
+        };
+        assert_eq!(resolve("user-key").unwrap(), ("acme".to_string(), false));
+        assert_eq!(resolve("admin-key").unwrap(), ("ops".to_string(), true));
+        assert!(resolve("other-key").is_err());
+    }
+}
@@ -0,0 +1,47 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use crate::config::FileSinkConfig;
+use crate::metrics;
+use super::{EventSink, ReceiptEvent};
+
+/// Appends events as JSON lines to a local file, synced after each event.
+pub struct FileSink {
+    file: Mutex<File>,
+}
+
+impl FileSink {
+    pub async fn new(cfg: &FileSinkConfig) -> Result<Self> {
+        if let Some(dir) = std::path::Path::new(&cfg.path).parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&cfg.path)
+            .await
+            .with_context(|| format!("Failed to open {}", cfg.path))?;
+        Ok(Self { file: Mutex::new(file) })
+    }
+}
+
+#[async_trait]
+impl EventSink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn publish(&self, event: &ReceiptEvent) -> Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        let write = async {
+            let mut file = self.file.lock().await;
+            file.write_all(&line).await?;
+            file.sync_data().await
+        };
+        metrics::timed("file", "publish", write).await?;
+        Ok(())
+    }
+}
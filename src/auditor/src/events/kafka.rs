@@ -0,0 +1,58 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use rdkafka::ClientConfig;
+use crate::config::KafkaConfig;
+use crate::metrics;
+use super::{EventSink, ReceiptEvent};
+
+pub struct KafkaProducer {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl KafkaProducer {
+    pub async fn new(cfg: &KafkaConfig) -> Result<Self> {
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", &cfg.brokers)
+            .set("message.timeout.ms", "5000")
+            .create()?;
+        Ok(Self {
+            producer,
+            topic: cfg.topic.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl EventSink for KafkaProducer {
+    fn name(&self) -> &'static str {
+        "kafka"
+    }
+
+    async fn publish(&self, event: &ReceiptEvent) -> Result<()> {
+        let payload = format!("{}:{}", hex::encode(&event.leaf_hash), event.receipt);
+        let key = hex::encode(&event.leaf_hash); // Store key in a variable to extend its lifetime
+        let record = FutureRecord::to(&self.topic)
+            .payload(&payload)
+            .key(&key);
+        let send = self.producer.send(record, std::time::Duration::from_secs(5));
+        metrics::timed("kafka", "publish", send).await
+            .map_err(|(e, _)| anyhow::anyhow!("Kafka error: {}", e))?;
+        Ok(())
+    }
+
+    /// Fetches topic metadata from the brokers.
+    async fn check(&self) -> Result<()> {
+        let producer = self.producer.clone();
+        let topic = self.topic.clone();
+        // librdkafka's metadata call blocks
+        tokio::task::spawn_blocking(move || {
+            producer.client()
+                .fetch_metadata(Some(&topic), std::time::Duration::from_secs(5))
+                .map(|_| ())
+        })
+        .await??;
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+//! Outbound notifications of integrated receipts. Each configured sink gets
+//! every event; `[events] sinks` selects which ones run.
+
+mod file;
+mod kafka;
+mod nats;
+mod webhook;
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use serde::Serialize;
+use crate::config::{Config, SinkKind};
+
+pub use file::FileSink;
+pub use kafka::KafkaProducer;
+pub use nats::NatsSink;
+pub use webhook::WebhookSink;
+
+/// A receipt whose leaf has been integrated into the log.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReceiptEvent {
+    pub tenant_id: String,
+    pub log_id: i64,
+    #[serde(serialize_with = "hex::serde::serialize")]
+    pub leaf_hash: Vec<u8>,
+    pub leaf_index: i64,
+    pub receipt: String,
+}
+
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    /// Name used in logs, metrics and error messages.
+    fn name(&self) -> &'static str;
+
+    async fn publish(&self, event: &ReceiptEvent) -> Result<()>;
+
+    /// Probes the sink's destination, for health checks.
+    async fn check(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Every configured sink, fed the same events. The outbox relay tracks
+/// delivery to each one separately, so one broken destination does not
+/// starve the others.
+pub struct EventSinks {
+    sinks: Vec<Box<dyn EventSink>>,
+}
+
+impl EventSinks {
+    pub async fn new(cfg: &Config) -> Result<Self> {
+        let mut sinks: Vec<Box<dyn EventSink>> = Vec::new();
+        for kind in &cfg.events.sinks {
+            let sink: Box<dyn EventSink> = match kind {
+                SinkKind::Kafka => Box::new(KafkaProducer::new(cfg.kafka.as_ref().context("[kafka] is not configured")?).await?),
+                SinkKind::Nats => Box::new(NatsSink::new(cfg.events.nats.as_ref().context("[events.nats] is not configured")?).await?),
+                SinkKind::Webhook => Box::new(WebhookSink::new(cfg.events.webhook.as_ref().context("[events.webhook] is not configured")?)?),
+                SinkKind::File => Box::new(FileSink::new(cfg.events.file.as_ref().context("[events.file] is not configured")?).await?),
+            };
+            sinks.push(sink);
+        }
+        Ok(Self::from_sinks(sinks))
+    }
+
+    pub fn from_sinks(sinks: Vec<Box<dyn EventSink>>) -> Self {
+        Self { sinks }
+    }
+
+    pub fn sinks(&self) -> &[Box<dyn EventSink>] {
+        &self.sinks
+    }
+
+    pub async fn check(&self) -> Result<()> {
+        let results = futures::future::join_all(self.sinks.iter().map(|sink| sink.check())).await;
+        failures(&self.sinks, results)
+    }
+}
+
+fn failures(sinks: &[Box<dyn EventSink>], results: Vec<Result<()>>) -> Result<()> {
+    let failed: Vec<String> = sinks.iter()
+        .zip(results)
+        .filter_map(|(sink, result)| result.err().map(|e| format!("{}: {}", sink.name(), e)))
+        .collect();
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        bail!("{}", failed.join("; "))
+    }
+}
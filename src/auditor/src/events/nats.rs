@@ -0,0 +1,46 @@
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use crate::config::NatsConfig;
+use crate::metrics;
+use super::{EventSink, ReceiptEvent};
+
+/// Publishes events as JSON on a NATS subject.
+pub struct NatsSink {
+    client: async_nats::Client,
+    subject: String,
+}
+
+impl NatsSink {
+    pub async fn new(cfg: &NatsConfig) -> Result<Self> {
+        let client = async_nats::connect(&cfg.url).await?;
+        Ok(Self {
+            client,
+            subject: cfg.subject.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl EventSink for NatsSink {
+    fn name(&self) -> &'static str {
+        "nats"
+    }
+
+    async fn publish(&self, event: &ReceiptEvent) -> Result<()> {
+        let payload = serde_json::to_vec(event)?;
+        let publish = async {
+            self.client.publish(self.subject.clone(), payload.into()).await?;
+            // Publishing only buffers; flushing surfaces connection errors
+            self.client.flush().await?;
+            Ok::<_, anyhow::Error>(())
+        };
+        metrics::timed("nats", "publish", publish).await
+    }
+
+    async fn check(&self) -> Result<()> {
+        match self.client.connection_state() {
+            async_nats::connection::State::Connected => Ok(()),
+            state => bail!("Not connected to NATS ({})", state),
+        }
+    }
+}
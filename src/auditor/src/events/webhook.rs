@@ -0,0 +1,76 @@
+use std::time::Duration;
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use crate::config::WebhookConfig;
+use crate::metrics;
+use super::{EventSink, ReceiptEvent};
+
+const TIMESTAMP_HEADER: &str = "X-VeriLLM-Timestamp";
+const SIGNATURE_HEADER: &str = "X-VeriLLM-Signature";
+
+/// POSTs events as JSON. Each request carries the Unix time it was sent and
+/// `sha256=<hex HMAC-SHA256 of "<timestamp>.<body>">` keyed with the shared
+/// secret, so receivers can authenticate it and reject replays.
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+    secret: Vec<u8>,
+}
+
+impl WebhookSink {
+    pub fn new(cfg: &WebhookConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(cfg.timeout_ms))
+            .build()?;
+        Ok(Self {
+            client,
+            url: cfg.url.clone(),
+            secret: cfg.secret.clone().into_bytes(),
+        })
+    }
+}
+
+#[async_trait]
+impl EventSink for WebhookSink {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn publish(&self, event: &ReceiptEvent) -> Result<()> {
+        let body = serde_json::to_vec(event)?;
+        let timestamp = chrono::Utc::now().timestamp().to_string();
+        let signature = sign(&self.secret, &timestamp, &body);
+        let request = self.client.post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(TIMESTAMP_HEADER, &timestamp)
+            .header(SIGNATURE_HEADER, format!("sha256={}", signature))
+            .body(body)
+            .send();
+        let response = metrics::timed("webhook", "publish", request).await?;
+        if !response.status().is_success() {
+            bail!("Webhook returned {}", response.status());
+        }
+        Ok(())
+    }
+}
+
+fn sign(secret: &[u8], timestamp: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_covers_timestamp_and_body() {
+        let signature = sign(b"secret", "1700000000", br#"{"leaf_index":7}"#);
+        assert_eq!(signature, "d024fd8f884d8cf7db8d9b6c5b901c07d65d73ac3adad5d93c24e061b481a1b7");
+    }
+}
@@ -0,0 +1,559 @@
+//! REST/JSON gateway for the `Auditor` service, following the HTTP bindings
+//! in `auditor.proto`. Requests are authenticated with the same API keys as
+//! gRPC and dispatched to the same service in-process. Hashes and other
+//! binary fields are hex encoded, as are COSE receipts; JWS and JSON receipts
+//! are returned as they are. The signing keys are also published, without
+//! authentication, as a JWKS document at `/.well-known/jwks.json`.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::TcpListener;
+use std::sync::Arc;
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    middleware::AddExtension,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use axum_server::accept::Accept;
+use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::server::TlsStream;
+use tonic::metadata::MetadataMap;
+use tower_layer::Layer;
+use crate::auditor::{
+    auditor_server::Auditor,
+    CheckpointRequest, ConsistencyProofRequest, HashSubmission, InclusionProofRequest,
+    LeavesByRangeRequest, ListReceiptsRequest, ReceiptFormat, ReceiptRequest, ReceiptResponse, ReceiptState,
+    Revocation, RevokeReceiptRequest,
+};
+use crate::auth::{ApiKeyAuth, Tenant};
+use crate::keys::Jwks;
+use crate::server::AuditorService;
+use crate::signer::Signer;
+use crate::tls::PeerIdentity;
+use verillm_shared_utils::telemetry;
+
+#[derive(Clone)]
+struct Gateway {
+    service: AuditorService,
+    auth: ApiKeyAuth,
+    signer: Arc<Signer>,
+}
+
+impl Gateway {
+    fn tenant(&self, headers: &HeaderMap) -> Result<Tenant, ApiError> {
+        Ok(self.auth.authenticate(&MetadataMap::from_headers(headers.clone()))?)
+    }
+
+    /// Wraps a message in a request carrying the caller's tenant, as the
+    /// gRPC interceptor would.
+    fn request<T>(&self, headers: &HeaderMap, message: T) -> Result<tonic::Request<T>, ApiError> {
+        let tenant = self.tenant(headers)?;
+        let mut request = tonic::Request::new(message);
+        request.extensions_mut().insert(tenant);
+        Ok(request)
+    }
+}
+
+pub fn router(service: AuditorService, auth: ApiKeyAuth, signer: Arc<Signer>) -> Router {
+    Router::new()
+        .route("/.well-known/jwks.json", get(get_jwks))
+        .route("/v1/receipts", get(list_receipts).post(submit_hash))
+        .route("/v1/receipts/:leaf_hash", get(get_receipt))
+        .route("/v1/receipts/:leaf_hash/revocation", post(revoke_receipt))
+        .route("/v1/checkpoint", get(get_latest_checkpoint))
+        .route("/v1/proofs/inclusion/:leaf_hash", get(get_inclusion_proof))
+        .route("/v1/proofs/consistency", get(get_consistency_proof))
+        .route("/v1/leaves", get(get_leaves_by_range))
+        .layer(axum::middleware::from_fn(telemetry::http_span))
+        .with_state(Gateway { service, auth, signer })
+}
+
+/// Serves the gateway on `listener` until `shutdown` resolves, over TLS
+/// when `tls` is given.
+pub async fn serve(
+    app: Router,
+    listener: TcpListener,
+    tls: Option<RustlsConfig>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let handle = axum_server::Handle::new();
+    let stop = handle.clone();
+    tokio::spawn(async move {
+        shutdown.await;
+        stop.graceful_shutdown(None);
+    });
+    let service = app.into_make_service();
+    match tls {
+        Some(tls) => {
+            let acceptor = ClientCertAcceptor { inner: RustlsAcceptor::new(tls) };
+            axum_server::from_tcp(listener).acceptor(acceptor).handle(handle).serve(service).await
+        }
+        None => axum_server::from_tcp(listener).handle(handle).serve(service).await,
+    }
+}
+
+/// The verified client certificate of a gateway connection, if the client
+/// presented one, as DER.
+#[derive(Clone)]
+struct ClientCertificate(Option<Vec<u8>>);
+
+/// Completes the TLS handshake and hands the connection's client
+/// certificate to its requests.
+#[derive(Clone)]
+struct ClientCertAcceptor {
+    inner: RustlsAcceptor,
+}
+
+impl<I, S> Accept<I, S> for ClientCertAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = TlsStream<I>;
+    type Service = AddExtension<S, ClientCertificate>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let acceptor = self.inner.clone();
+        Box::pin(async move {
+            let (stream, service) = acceptor.accept(stream, service).await?;
+            let leaf = stream.get_ref().1.peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(|cert| cert.0.clone());
+            Ok((stream, Extension(ClientCertificate(leaf)).layer(service)))
+        })
+    }
+}
+
+/// gRPC status rendered as an HTTP error with a JSON body.
+struct ApiError(tonic::Status);
+
+impl From<tonic::Status> for ApiError {
+    fn from(status: tonic::Status) -> Self {
+        Self(status)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = http_status(self.0.code());
+        let body = Json(serde_json::json!({
+            "code": self.0.code() as i32,
+            "message": self.0.message(),
+        }));
+        (status, body).into_response()
+    }
+}
+
+fn http_status(code: tonic::Code) -> StatusCode {
+    use tonic::Code;
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::InvalidArgument | Code::OutOfRange | Code::FailedPrecondition => StatusCode::BAD_REQUEST,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn hex_arg(value: &str) -> Result<Vec<u8>, ApiError> {
+    hex::decode(value)
+        .map_err(|e| tonic::Status::invalid_argument(format!("Invalid hex: {}", e)).into())
+}
+
+#[derive(Debug, Serialize)]
+struct ReceiptJson {
+    leaf_hash: String,
+    leaf_index: u64,
+    log_id: i64,
+    state: &'static str,
+    duplicate: bool,
+    receipt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revocation: Option<RevocationJson>,
+}
+
+#[derive(Debug, Serialize)]
+struct RevocationJson {
+    reason: String,
+    actor: String,
+    revoked_at_ns: u64,
+    statement: String,
+    revocation_leaf_hash: String,
+    log_id: i64,
+}
+
+impl From<Revocation> for RevocationJson {
+    fn from(revocation: Revocation) -> Self {
+        Self {
+            reason: revocation.reason,
+            actor: revocation.actor,
+            revoked_at_ns: revocation.revoked_at_ns,
+            statement: String::from_utf8_lossy(&revocation.statement).into_owned(),
+            revocation_leaf_hash: hex::encode(&revocation.revocation_leaf_hash),
+            log_id: revocation.log_id,
+        }
+    }
+}
+
+impl From<ReceiptResponse> for ReceiptJson {
+    fn from(response: ReceiptResponse) -> Self {
+        let state = match ReceiptState::try_from(response.state) {
+            Ok(ReceiptState::Pending) => "pending",
+            Ok(ReceiptState::Integrated) => "integrated",
+            _ => "unspecified",
+        };
+        Self {
+            leaf_hash: hex::encode(&response.leaf_hash),
+            leaf_index: response.leaf_index,
+            log_id: response.log_id,
+            state,
+            duplicate: response.duplicate,
+            receipt: String::from_utf8_lossy(&response.receipt).into_owned(),
+            revocation: response.revocation.map(RevocationJson::from),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SubmitBody {
+    hash: String,
+    metadata: serde_json::Value,
+    proxy_id: String,
+    #[serde(default)]
+    timestamp_ns: u64,
+}
+
+async fn submit_hash(
+    State(gw): State<Gateway>,
+    client_cert: Option<Extension<ClientCertificate>>,
+    headers: HeaderMap,
+    Json(body): Json<SubmitBody>,
+) -> Result<Json<ReceiptJson>, ApiError> {
+    let Tenant(tenant_id) = gw.tenant(&headers)?;
+    let identity = match client_cert {
+        Some(Extension(ClientCertificate(Some(der)))) => Some(PeerIdentity::from_certificate(&der)
+            .map_err(|e| tonic::Status::unauthenticated(format!("Invalid client certificate: {}", e)))?),
+        _ => None,
+    };
+    let submission = HashSubmission {
+        hash: hex_arg(&body.hash)?,
+        metadata: serde_json::to_vec(&body.metadata)
+            .map_err(|e| tonic::Status::invalid_argument(e.to_string()))?,
+        proxy_id: body.proxy_id,
+        timestamp_ns: body.timestamp_ns,
+        // The request's own span, from its headers, is the parent
+        trace_context: HashMap::new(),
+    };
+    let response = gw.service.submit_one(tenant_id, identity, submission).await?;
+    // A per-item failure on the stream is the request's failure here
+    if let Some(status) = &response.status {
+        let code = tonic::Code::from_i32(status.code);
+        return Err(tonic::Status::new(code, status.message.clone()).into());
+    }
+    Ok(Json(response.into()))
+}
+
+#[derive(Debug, Deserialize)]
+struct ReceiptParams {
+    /// "jws" (the default) or "cose".
+    #[serde(default)]
+    format: Option<String>,
+}
+
+async fn get_receipt(
+    State(gw): State<Gateway>,
+    headers: HeaderMap,
+    Path(leaf_hash): Path<String>,
+    Query(params): Query<ReceiptParams>,
+) -> Result<Json<ReceiptJson>, ApiError> {
+    let format = match params.format.as_deref() {
+        None | Some("jws") => ReceiptFormat::Jws,
+        Some("cose") => ReceiptFormat::Cose,
+        Some(other) => return Err(tonic::Status::invalid_argument(format!("Unknown receipt format {:?}", other)).into()),
+    };
+    let message = ReceiptRequest {
+        leaf_hash: hex_arg(&leaf_hash)?,
+        format: format as i32,
+    };
+    let response = gw.service.get_receipt(gw.request(&headers, message)?).await?.into_inner();
+    let cose_receipt = (format == ReceiptFormat::Cose).then(|| hex::encode(&response.receipt));
+    let mut receipt = ReceiptJson::from(response);
+    if let Some(cose_receipt) = cose_receipt {
+        receipt.receipt = cose_receipt;
+    }
+    Ok(Json(receipt))
+}
+
+#[derive(Debug, Deserialize)]
+struct RevokeBody {
+    reason: String,
+    #[serde(default)]
+    actor: String,
+}
+
+async fn revoke_receipt(
+    State(gw): State<Gateway>,
+    headers: HeaderMap,
+    Path(leaf_hash): Path<String>,
+    Json(body): Json<RevokeBody>,
+) -> Result<Json<RevocationJson>, ApiError> {
+    let message = RevokeReceiptRequest {
+        leaf_hash: hex_arg(&leaf_hash)?,
+        reason: body.reason,
+        actor: body.actor,
+    };
+    let revocation = gw.service.revoke_receipt(gw.request(&headers, message)?).await?.into_inner();
+    Ok(Json(revocation.into()))
+}
+
+#[derive(Debug, Serialize)]
+struct ReceiptPage {
+    receipts: Vec<ReceiptJson>,
+    next_page_token: String,
+}
+
+/// Query parameters map onto `ListReceiptsRequest` fields; `context.<path>`
+/// parameters become context filters.
+async fn list_receipts(
+    State(gw): State<Gateway>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<ReceiptPage>, ApiError> {
+    let number = |name: &str| -> Result<u64, ApiError> {
+        match params.get(name) {
+            Some(value) => value.parse()
+                .map_err(|_| tonic::Status::invalid_argument(format!("Invalid {}", name)).into()),
+            None => Ok(0),
+        }
+    };
+    let message = ListReceiptsRequest {
+        created_after_ns: number("created_after_ns")?,
+        created_before_ns: number("created_before_ns")?,
+        model_id: params.get("model_id").cloned().unwrap_or_default(),
+        proxy_id: params.get("proxy_id").cloned().unwrap_or_default(),
+        context: params.iter()
+            .filter_map(|(key, value)| key.strip_prefix("context.").map(|path| (path.to_string(), value.clone())))
+            .collect(),
+        page_size: u32::try_from(number("page_size")?)
+            .map_err(|_| tonic::Status::invalid_argument("Invalid page_size"))?,
+        page_token: params.get("page_token").cloned().unwrap_or_default(),
+    };
+    let response = gw.service.list_receipts(gw.request(&headers, message)?).await?.into_inner();
+    Ok(Json(ReceiptPage {
+        receipts: response.receipts.into_iter().map(ReceiptJson::from).collect(),
+        next_page_token: response.next_page_token,
+    }))
+}
+
+async fn get_latest_checkpoint(
+    State(gw): State<Gateway>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let checkpoint = gw.service.get_latest_checkpoint(gw.request(&headers, CheckpointRequest {})?).await?.into_inner();
+    Ok(Json(serde_json::json!({
+        "log_id": checkpoint.log_id,
+        "tree_size": checkpoint.tree_size,
+        "root_hash": hex::encode(&checkpoint.root_hash),
+        "timestamp_nanos": checkpoint.timestamp_nanos,
+        "log_root": hex::encode(&checkpoint.log_root),
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct InclusionParams {
+    #[serde(default)]
+    tree_size: u64,
+}
+
+async fn get_inclusion_proof(
+    State(gw): State<Gateway>,
+    headers: HeaderMap,
+    Path(leaf_hash): Path<String>,
+    Query(params): Query<InclusionParams>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let message = InclusionProofRequest {
+        leaf_hash: hex_arg(&leaf_hash)?,
+        tree_size: params.tree_size,
+    };
+    let proof = gw.service.get_inclusion_proof(gw.request(&headers, message)?).await?.into_inner();
+    Ok(Json(serde_json::json!({
+        "log_id": proof.log_id,
+        "leaf_index": proof.leaf_index,
+        "tree_size": proof.tree_size,
+        "hashes": proof.hashes.iter().map(hex::encode).collect::<Vec<_>>(),
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct ConsistencyParams {
+    first_tree_size: u64,
+    second_tree_size: u64,
+}
+
+async fn get_consistency_proof(
+    State(gw): State<Gateway>,
+    headers: HeaderMap,
+    Query(params): Query<ConsistencyParams>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let message = ConsistencyProofRequest {
+        first_tree_size: params.first_tree_size,
+        second_tree_size: params.second_tree_size,
+    };
+    let proof = gw.service.get_consistency_proof(gw.request(&headers, message)?).await?.into_inner();
+    Ok(Json(serde_json::json!({
+        "log_id": proof.log_id,
+        "first_tree_size": proof.first_tree_size,
+        "second_tree_size": proof.second_tree_size,
+        "hashes": proof.hashes.iter().map(hex::encode).collect::<Vec<_>>(),
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct RangeParams {
+    start_index: u64,
+    count: u64,
+}
+
+async fn get_leaves_by_range(
+    State(gw): State<Gateway>,
+    headers: HeaderMap,
+    Query(params): Query<RangeParams>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let message = LeavesByRangeRequest {
+        start_index: params.start_index,
+        count: params.count,
+    };
+    let range = gw.service.get_leaves_by_range(gw.request(&headers, message)?).await?.into_inner();
+    let leaves: Vec<serde_json::Value> = range.leaves.iter()
+        .map(|leaf| serde_json::json!({
+            "leaf_index": leaf.leaf_index,
+            "leaf_value": hex::encode(&leaf.leaf_value),
+            "merkle_leaf_hash": hex::encode(&leaf.merkle_leaf_hash),
+        }))
+        .collect();
+    Ok(Json(serde_json::json!({
+        "log_id": range.log_id,
+        "leaves": leaves,
+    })))
+}
+
+async fn get_jwks(State(gw): State<Gateway>) -> Json<Jwks> {
+    Json(gw.signer.jwks())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TlsConfig;
+    use crate::tls;
+
+    #[tokio::test]
+    async fn test_gateway_serves_tls_with_server_certificate() {
+        let dir = std::env::temp_dir().join(format!("verillm-gateway-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let tls_cfg = TlsConfig {
+            cert_path: dir.join("cert.pem").display().to_string(),
+            key_path: dir.join("key.pem").display().to_string(),
+            client_ca_path: None,
+        };
+        std::fs::write(&tls_cfg.cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&tls_cfg.key_path, cert.serialize_private_key_pem()).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new().route("/ping", get(|| async { "pong" }));
+        let tls = tls::gateway_tls_config(&tls_cfg).await.unwrap();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(app, listener, Some(tls), async {
+            let _ = stop_rx.await;
+        }));
+
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        let response = client.get(format!("https://127.0.0.1:{}/ping", port)).send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "pong");
+        // Plain HTTP is not answered
+        assert!(client.get(format!("http://127.0.0.1:{}/ping", port)).send().await.is_err());
+
+        stop_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_gateway_hands_verified_client_certificate_to_requests() {
+        let dir = std::env::temp_dir().join(format!("verillm-gateway-mtls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut ca_params = rcgen::CertificateParams::new(Vec::new());
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = rcgen::Certificate::from_params(ca_params).unwrap();
+        let server_cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let client_cert = rcgen::generate_simple_self_signed(vec!["proxy-1".to_string()]).unwrap();
+        let tls_cfg = TlsConfig {
+            cert_path: dir.join("cert.pem").display().to_string(),
+            key_path: dir.join("key.pem").display().to_string(),
+            client_ca_path: Some(dir.join("ca.pem").display().to_string()),
+        };
+        std::fs::write(&tls_cfg.cert_path, server_cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&tls_cfg.key_path, server_cert.serialize_private_key_pem()).unwrap();
+        std::fs::write(tls_cfg.client_ca_path.as_ref().unwrap(), ca.serialize_pem().unwrap()).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new().route("/whoami", get(|client_cert: Option<Extension<ClientCertificate>>| async move {
+            match client_cert {
+                Some(Extension(ClientCertificate(Some(der)))) => {
+                    PeerIdentity::from_certificate(&der).unwrap().authorizes("proxy-1").to_string()
+                }
+                _ => "anonymous".to_string(),
+            }
+        }));
+        let tls = tls::gateway_tls_config(&tls_cfg).await.unwrap();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(app, listener, Some(tls), async {
+            let _ = stop_rx.await;
+        }));
+        let url = format!("https://127.0.0.1:{}/whoami", port);
+
+        let identity = reqwest::Identity::from_pkcs8_pem(
+            client_cert.serialize_pem_with_signer(&ca).unwrap().as_bytes(),
+            client_cert.serialize_private_key_pem().as_bytes(),
+        )
+        .unwrap();
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .identity(identity)
+            .build()
+            .unwrap();
+        assert_eq!(client.get(&url).send().await.unwrap().text().await.unwrap(), "true");
+
+        // Callers without a certificate still connect, to read with an API key
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        assert_eq!(client.get(&url).send().await.unwrap().text().await.unwrap(), "anonymous");
+
+        stop_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
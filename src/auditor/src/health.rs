@@ -0,0 +1,74 @@
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::time::{self, Duration};
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+use tracing::warn;
+use crate::auditor::auditor_server::AuditorServer;
+use crate::events::EventSinks;
+use crate::server::AuditorService;
+use crate::storage::Storage;
+use crate::translog::TransparencyLog;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Health service names reported for each dependency, alongside the
+/// `auditor.Auditor` service itself, which is serving only while all of
+/// them are.
+const DATABASE: &str = "database";
+const LOG: &str = "log";
+const EVENTS: &str = "events";
+
+/// Probes the auditor's dependencies and publishes the results on the gRPC
+/// health service until shutdown, when everything is marked not serving so
+/// load balancers stop routing here while queued work drains.
+pub async fn monitor(
+    mut reporter: HealthReporter,
+    storage: Arc<dyn Storage>,
+    log: Arc<dyn TransparencyLog>,
+    log_id: i64,
+    events: Arc<EventSinks>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut interval = time::interval(CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let (database, root, events) = tokio::join!(
+                    storage.ping(),
+                    log.latest_root(log_id),
+                    events.check(),
+                );
+                let database = report(&mut reporter, DATABASE, database.map(|_| ())).await;
+                let root = report(&mut reporter, LOG, root.map(|_| ())).await;
+                let events = report(&mut reporter, EVENTS, events).await;
+                if database && root && events {
+                    reporter.set_serving::<AuditorServer<AuditorService>>().await;
+                } else {
+                    reporter.set_not_serving::<AuditorServer<AuditorService>>().await;
+                }
+            }
+            _ = shutdown.changed() => {
+                reporter.set_not_serving::<AuditorServer<AuditorService>>().await;
+                for name in [DATABASE, LOG, EVENTS] {
+                    reporter.set_service_status(name, ServingStatus::NotServing).await;
+                }
+                return;
+            }
+        }
+    }
+}
+
+async fn report(reporter: &mut HealthReporter, name: &str, result: anyhow::Result<()>) -> bool {
+    match result {
+        Ok(()) => {
+            reporter.set_service_status(name, ServingStatus::Serving).await;
+            true
+        }
+        Err(e) => {
+            warn!("Health check for {} failed: {}", name, e);
+            reporter.set_service_status(name, ServingStatus::NotServing).await;
+            false
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use anyhow::Result;
+use tokio::sync::broadcast;
+use tokio::time::{self, Duration};
+use tracing::{debug, error, info, warn};
+use crate::auditor::{ReceiptResponse, ReceiptState};
+use crate::config::IntegratorConfig;
+use crate::routing::LogRouter;
+use crate::signer::Signer;
+use crate::storage::{PendingRecord, Storage};
+use crate::translog::{InclusionProof, SignedRoot, TransparencyLog};
+
+/// An upgraded receipt, announced to the streams of the tenant that owns it.
+#[derive(Debug, Clone)]
+pub struct ReceiptUpdate {
+    pub tenant_id: String,
+    pub response: ReceiptResponse,
+}
+
+/// Upgrades pending receipts once their leaves are covered by a signed log
+/// root, and announces the upgraded receipts to in-process subscribers.
+/// Event sinks hear about them through the outbox.
+pub struct Integrator {
+    log: Arc<dyn TransparencyLog>,
+    router: Arc<LogRouter>,
+    signer: Arc<Signer>,
+    storage: Arc<dyn Storage>,
+    updates: broadcast::Sender<ReceiptUpdate>,
+    cfg: IntegratorConfig,
+}
+
+impl Integrator {
+    pub fn new(
+        log: Arc<dyn TransparencyLog>,
+        router: Arc<LogRouter>,
+        signer: Arc<Signer>,
+        storage: Arc<dyn Storage>,
+        updates: broadcast::Sender<ReceiptUpdate>,
+        cfg: IntegratorConfig,
+    ) -> Self {
+        Self { log, router, signer, storage, updates, cfg }
+    }
+
+    pub async fn run(self) {
+        let mut interval = time::interval(Duration::from_millis(self.cfg.poll_interval_ms));
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.integrate_pending().await {
+                error!("Failed to integrate pending receipts: {}", e);
+            }
+        }
+    }
+
+    async fn integrate_pending(&self) -> Result<()> {
+        let pending = self.storage.pending_receipts(self.cfg.batch_size).await?;
+
+        let mut by_log: HashMap<i64, Vec<PendingRecord>> = HashMap::new();
+        for record in pending {
+            let log_id = record.log_id.unwrap_or(self.router.default_log_id());
+            by_log.entry(log_id).or_default().push(record);
+        }
+        for (log_id, records) in by_log {
+            if let Err(e) = self.integrate_log(log_id, records).await {
+                error!("Failed to integrate pending receipts for log {}: {}", log_id, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks one tree's pending leaves against a single signed root.
+    async fn integrate_log(&self, log_id: i64, pending: Vec<PendingRecord>) -> Result<()> {
+        let signed_root = self.log.latest_root(log_id).await?;
+        let proofs = futures::future::join_all(pending.iter().map(|record| {
+            self.log.inclusion_proof(log_id, &record.leaf_hash, signed_root.tree_size)
+        }))
+        .await;
+
+        for (record, proof) in pending.into_iter().zip(proofs) {
+            match proof {
+                Ok(Some(proof)) => match self.upgrade(log_id, &record, proof, &signed_root).await {
+                    Ok(()) => continue,
+                    Err(e) => error!("Failed to upgrade receipt {}: {}", hex::encode(&record.leaf_hash), e),
+                },
+                Ok(None) => self.check_merge_delay(&record),
+                Err(e) => error!("Failed to fetch proof for {}: {}", hex::encode(&record.leaf_hash), e),
+            }
+            self.defer(&record).await;
+        }
+        Ok(())
+    }
+
+    /// Puts off the next check of a leaf that did not integrate, for longer
+    /// each time, so the receipts behind it get their turn.
+    async fn defer(&self, record: &PendingRecord) {
+        let doublings = record.attempts.clamp(0, 20) as u32;
+        let delay_ms = self.cfg.poll_interval_ms.saturating_mul(1 << doublings).min(self.cfg.max_retry_interval_ms);
+        let retry_at = chrono::Utc::now() + chrono::Duration::milliseconds(delay_ms as i64);
+        if let Err(e) = self.storage.defer_pending(&record.tenant_id, &record.leaf_hash, retry_at).await {
+            error!("Failed to defer receipt {}: {}", hex::encode(&record.leaf_hash), e);
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(
+        log_id = log_id,
+        leaf_hash = %hex::encode(&record.leaf_hash),
+        leaf_index = proof.leaf_index,
+    ))]
+    async fn upgrade(&self, log_id: i64, record: &PendingRecord, proof: InclusionProof, signed_root: &SignedRoot) -> Result<()> {
+        let metadata = serde_json::to_vec(&record.context)?;
+        let receipt_jwt = self.signer.sign_receipt(
+            log_id,
+            &record.leaf_hash,
+            &proof,
+            signed_root,
+            &metadata,
+        ).await?;
+        self.storage.mark_integrated(
+            &record.tenant_id,
+            log_id,
+            &record.leaf_hash,
+            proof.leaf_index,
+            &signed_root.root_hash,
+            &receipt_jwt,
+        ).await?;
+        info!("Receipt {} integrated at index {}", hex::encode(&record.leaf_hash), proof.leaf_index);
+
+        // No receivers just means no stream is waiting on this leaf
+        let _ = self.updates.send(ReceiptUpdate {
+            tenant_id: record.tenant_id.clone(),
+            response: ReceiptResponse {
+                receipt: receipt_jwt.into_bytes(),
+                leaf_index: proof.leaf_index as u64,
+                leaf_hash: record.leaf_hash.clone(),
+                status: None,
+                state: ReceiptState::Integrated as i32,
+                duplicate: false,
+                log_id,
+                revocation: None,
+            },
+        });
+        Ok(())
+    }
+
+    fn check_merge_delay(&self, record: &PendingRecord) {
+        let waited = chrono::Utc::now() - record.created_at;
+        if waited.num_seconds() > self.cfg.max_merge_delay_secs as i64 {
+            warn!(
+                "Leaf {} not integrated after {}s, exceeding the promised merge delay",
+                hex::encode(&record.leaf_hash),
+                waited.num_seconds()
+            );
+        } else {
+            debug!("Leaf {} not integrated yet", hex::encode(&record.leaf_hash));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DatabaseConfig, SigningConfig, TrillianConfig};
+    use crate::merkle_log::{DatabaseLeafStore, MerkleLog};
+    use crate::storage::{SqliteStorage, STATUS_INTEGRATED, STATUS_PENDING};
+
+    #[tokio::test]
+    async fn test_upgrade_queues_event_with_receipt_and_broadcasts_it() {
+        let storage: Arc<dyn Storage> = Arc::new(
+            SqliteStorage::new(&DatabaseConfig { url: "sqlite::memory:".to_string(), password: None }).await.unwrap(),
+        );
+        let log = Arc::new(MerkleLog::new(Box::new(DatabaseLeafStore::new(storage.clone()))));
+        let router = Arc::new(LogRouter::new(&TrillianConfig::default(), storage.clone()));
+        let signer = Arc::new(Signer::new(&SigningConfig::default()).await.unwrap());
+        let (updates, mut receiver) = broadcast::channel(8);
+        let integrator = Integrator::new(log.clone(), router, signer, storage.clone(), updates, IntegratorConfig::default());
+
+        let leaf_hash = vec![7; 32];
+        storage.store_pending_receipt("tenant", 3, "proxy", &leaf_hash, b"{}", "promise").await.unwrap();
+        log.append(3, std::slice::from_ref(&leaf_hash)).await.unwrap();
+        integrator.integrate_pending().await.unwrap();
+
+        // The event is committed with the receipt, so no sink can lose it
+        // or hold up the stream
+        let update = receiver.try_recv().unwrap();
+        assert_eq!(update.tenant_id, "tenant");
+        assert_eq!(update.response.state, ReceiptState::Integrated as i32);
+        let events = storage.undelivered_events("kafka", 10).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].log_id, events[0].leaf_index), (3, 0));
+        assert_eq!(events[0].receipt_jwt.as_bytes(), update.response.receipt.as_slice());
+        assert_eq!(storage.get_receipt("tenant", &leaf_hash).await.unwrap().unwrap().status, STATUS_INTEGRATED);
+    }
+
+    #[tokio::test]
+    async fn test_leaf_missing_from_the_log_does_not_hold_up_later_ones() {
+        let storage: Arc<dyn Storage> = Arc::new(
+            SqliteStorage::new(&DatabaseConfig { url: "sqlite::memory:".to_string(), password: None }).await.unwrap(),
+        );
+        let log = Arc::new(MerkleLog::new(Box::new(DatabaseLeafStore::new(storage.clone()))));
+        let router = Arc::new(LogRouter::new(&TrillianConfig::default(), storage.clone()));
+        let signer = Arc::new(Signer::new(&SigningConfig::default()).await.unwrap());
+        let cfg = IntegratorConfig { batch_size: 1, poll_interval_ms: 60_000, ..IntegratorConfig::default() };
+        let integrator = Integrator::new(log.clone(), router, signer, storage.clone(), broadcast::channel(8).0, cfg);
+
+        // The older leaf never reaches the log
+        let (missing, appended) = (vec![1; 32], vec![2; 32]);
+        storage.store_pending_receipt("tenant", 3, "proxy", &missing, b"{}", "promise").await.unwrap();
+        time::sleep(Duration::from_millis(2)).await;
+        storage.store_pending_receipt("tenant", 3, "proxy", &appended, b"{}", "promise").await.unwrap();
+        log.append(3, std::slice::from_ref(&appended)).await.unwrap();
+
+        integrator.integrate_pending().await.unwrap();
+        integrator.integrate_pending().await.unwrap();
+        assert_eq!(storage.get_receipt("tenant", &appended).await.unwrap().unwrap().status, STATUS_INTEGRATED);
+        assert_eq!(storage.get_receipt("tenant", &missing).await.unwrap().unwrap().status, STATUS_PENDING);
+        // And it waits out its delay rather than being checked every poll
+        assert!(storage.pending_receipts(10).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,224 @@
+//! Ed25519 signing keys: loading them from PKCS#8 files, choosing the one
+//! that signs at a given moment, and publishing all of them as a JWKS
+//! document so receipts can be checked against a stable set of keys.
+
+use std::path::Path;
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use ed25519_dalek::pkcs8::DecodePrivateKey;
+use ed25519_dalek::SigningKey;
+use rand::rngs::OsRng;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+use verillm_shared_utils::statements::Jwk;
+use crate::config::SigningConfig;
+
+pub struct Key {
+    pub kid: String,
+    pub signing_key: SigningKey,
+    not_before: Option<DateTime<Utc>>,
+    not_after: Option<DateTime<Utc>>,
+}
+
+impl Key {
+    /// A fresh key with no validity window.
+    pub fn generate() -> Self {
+        let signing_key = SigningKey::generate(&mut OsRng);
+        Key { kid: thumbprint(&signing_key), signing_key, not_before: None, not_after: None }
+    }
+
+    pub fn jwk(&self) -> Jwk {
+        Jwk::ed25519(&self.kid, &self.signing_key.verifying_key())
+    }
+
+    fn valid_at(&self, at: DateTime<Utc>) -> bool {
+        self.not_before.map_or(true, |not_before| not_before <= at)
+            && self.not_after.map_or(true, |not_after| at < not_after)
+    }
+}
+
+/// Every configured key, ordered by when it starts signing and then by kid.
+pub struct KeyRing {
+    keys: Vec<Key>,
+}
+
+/// JSON Web Key Set (RFC 7517) of the public signing keys.
+#[derive(Debug, Serialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+impl KeyRing {
+    pub fn load(cfg: &SigningConfig) -> Result<Self> {
+        let mut keys = Vec::new();
+        if let Some(key_dir) = &cfg.key_dir {
+            let mut paths = std::fs::read_dir(key_dir)
+                .with_context(|| format!("Reading signing key directory {}", key_dir))?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            paths.sort();
+            for path in paths {
+                if path.extension().map_or(true, |extension| extension != "pem") {
+                    continue;
+                }
+                let kid = path.file_stem().and_then(|stem| stem.to_str())
+                    .ok_or_else(|| anyhow!("Signing key {} has no usable name", path.display()))?;
+                keys.push(Key {
+                    kid: kid.to_string(),
+                    signing_key: read_key(&path)?,
+                    not_before: None,
+                    not_after: None,
+                });
+            }
+        }
+
+        for key_cfg in &cfg.keys {
+            let key = match &key_cfg.path {
+                Some(path) => {
+                    let signing_key = read_key(Path::new(path))?;
+                    let kid = key_cfg.kid.clone().unwrap_or_else(|| thumbprint(&signing_key));
+                    if keys.iter().any(|key| key.kid == kid) {
+                        bail!("Signing key {} is configured twice", kid);
+                    }
+                    keys.push(Key { kid, signing_key, not_before: None, not_after: None });
+                    keys.last_mut().unwrap()
+                }
+                None => {
+                    let kid = key_cfg.kid.as_deref().unwrap_or_default();
+                    keys.iter_mut().find(|key| key.kid == kid)
+                        .ok_or_else(|| anyhow!("Signing key {} is not in signing.key_dir", kid))?
+                }
+            };
+            key.not_before = key_cfg.not_before;
+            key.not_after = key_cfg.not_after;
+        }
+
+        if keys.is_empty() {
+            warn!("No signing keys configured; using a throwaway key, so receipts will not verify after a restart");
+            keys.push(Key::generate());
+        }
+
+        let ring = Self::new(keys)?;
+        let now = Utc::now();
+        let active = ring.active_at(now)?;
+        info!("Loaded {} signing keys; {} is signing", ring.keys.len(), active.kid);
+        if let Some(next) = ring.keys.iter().find(|key| key.not_before.map_or(false, |not_before| not_before > now)) {
+            info!("Signing key {} takes over at {}", next.kid, next.not_before.unwrap().to_rfc3339());
+        }
+        Ok(ring)
+    }
+
+    fn new(mut keys: Vec<Key>) -> Result<Self> {
+        // Absent `not_before` sorts first. Keys starting together are ordered
+        // by kid, so the same one signs on every replica and restart.
+        keys.sort_by(|a, b| a.not_before.cmp(&b.not_before).then_with(|| a.kid.cmp(&b.kid)));
+        for pair in keys.windows(2) {
+            // Only a schedule that starts two keys at once while both are
+            // valid is contradictory; keys with no window are just ordered
+            let Some(not_before) = pair[0].not_before else {
+                continue;
+            };
+            if pair[1].not_before == Some(not_before) && pair[0].valid_at(not_before) && pair[1].valid_at(not_before) {
+                bail!(
+                    "Signing keys {} and {} are both scheduled to start signing at {}; give the newer one a later not_before",
+                    pair[0].kid,
+                    pair[1].kid,
+                    not_before.to_rfc3339()
+                );
+            }
+        }
+        Ok(Self { keys })
+    }
+
+    /// The key that signs at `at`: of the keys valid then, the one that
+    /// became valid last.
+    pub fn active_at(&self, at: DateTime<Utc>) -> Result<&Key> {
+        self.keys.iter().rev()
+            .find(|key| key.valid_at(at))
+            .ok_or_else(|| anyhow!("No signing key is valid at {}", at.to_rfc3339()))
+    }
+
+    /// Active, scheduled and retired keys alike.
+    pub fn jwks(&self) -> Jwks {
+        Jwks {
+            keys: self.keys.iter().map(Key::jwk).collect(),
+        }
+    }
+}
+
+fn read_key(path: &Path) -> Result<SigningKey> {
+    let contents = std::fs::read(path)
+        .with_context(|| format!("Reading signing key {}", path.display()))?;
+    let key = match std::str::from_utf8(&contents) {
+        Ok(pem) if pem.trim_start().starts_with("-----BEGIN") => SigningKey::from_pkcs8_pem(pem),
+        _ => SigningKey::from_pkcs8_der(&contents),
+    };
+    key.map_err(|e| anyhow!("Invalid PKCS#8 Ed25519 key {}: {}", path.display(), e))
+}
+
+/// RFC 7638 thumbprint of the key's public JWK.
+fn thumbprint(key: &SigningKey) -> String {
+    // Only the required members, in lexicographic order, without whitespace
+    let jwk = format!(
+        r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#,
+        BASE64URL.encode(key.verifying_key().as_bytes())
+    );
+    BASE64URL.encode(Sha256::digest(jwk.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(kid: &str, not_before: Option<&str>, not_after: Option<&str>) -> Key {
+        let parse = |at: &str| at.parse::<DateTime<Utc>>().unwrap();
+        Key {
+            kid: kid.to_string(),
+            signing_key: SigningKey::generate(&mut OsRng),
+            not_before: not_before.map(parse),
+            not_after: not_after.map(parse),
+        }
+    }
+
+    #[test]
+    fn test_thumbprint_matches_rfc8037() {
+        let secret = BASE64URL.decode("nWGxne_9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A").unwrap();
+        let key = SigningKey::from_bytes(secret.as_slice().try_into().unwrap());
+        assert_eq!(thumbprint(&key), "kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k");
+    }
+
+    #[test]
+    fn test_successor_signs_once_its_window_opens() {
+        let ring = KeyRing::new(vec![
+            key("2025-06", Some("2025-06-01T00:00:00Z"), None),
+            key("2025-01", None, Some("2025-07-01T00:00:00Z")),
+        ])
+        .unwrap();
+        let active = |at: &str| ring.active_at(at.parse().unwrap()).unwrap().kid.clone();
+        assert_eq!(active("2025-05-31T23:59:59Z"), "2025-01");
+        // Both are valid during the overlap; the newer one signs
+        assert_eq!(active("2025-06-15T00:00:00Z"), "2025-06");
+        assert_eq!(active("2025-08-01T00:00:00Z"), "2025-06");
+        assert_eq!(ring.jwks().keys.len(), 2);
+    }
+
+    #[test]
+    fn test_keys_starting_together_are_ordered_by_kid_unless_scheduled_to_overlap() {
+        // Keys from signing.key_dir have no window; the last kid signs
+        let ring = KeyRing::new(vec![key("b", None, None), key("c", None, None), key("a", None, None)]).unwrap();
+        assert_eq!(ring.active_at(Utc::now()).unwrap().kid, "c");
+        // As are retired keys alongside their successor
+        let ring = KeyRing::new(vec![key("old", None, Some("2025-06-01T00:00:00Z")), key("new", None, None)]).unwrap();
+        assert_eq!(ring.active_at("2025-05-01T00:00:00Z".parse().unwrap()).unwrap().kid, "old");
+        assert_eq!(ring.active_at("2025-07-01T00:00:00Z".parse().unwrap()).unwrap().kid, "new");
+
+        assert!(KeyRing::new(vec![
+            key("a", Some("2025-06-01T00:00:00Z"), None),
+            key("b", Some("2025-06-01T00:00:00Z"), Some("2025-12-01T00:00:00Z")),
+        ])
+        .is_err());
+    }
+}
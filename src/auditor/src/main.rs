@@ -1,14 +1,32 @@
+mod admin;
+mod auth;
 mod config;
+mod gateway;
+mod health;
+mod integrator;
+mod keys;
 mod server;
 mod signer;
 mod storage;
-mod kafka;
+mod tls;
+mod events;
+mod merkle_log;
+mod metrics;
+mod outbox;
+mod ratelimit;
+mod retry;
+mod routing;
+mod translog;
 mod trillian;
 
 pub mod auditor {
     include!(concat!(env!("OUT_DIR"), "/auditor.rs"));
 }
 
+/// Served over gRPC reflection.
+pub const AUDITOR_DESCRIPTOR_SET: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/auditor_descriptor.bin"));
+
 pub mod google {
     pub mod rpc {
         include!(concat!(env!("OUT_DIR"), "/google.rpc.rs"));
@@ -16,12 +34,19 @@ pub mod google {
 }
 
 use anyhow::Result;
-use tracing_subscriber;
+use clap::Parser;
+use verillm_shared_utils::telemetry;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
-    let cfg = config::Config::from_env()?;
-    server::run(cfg).await?;
-    Ok(())
+    let cli = config::Cli::parse();
+    let cfg = config::Config::load(&cli)?;
+    if cli.print_config {
+        print!("{}", cfg.to_masked_toml()?);
+        return Ok(());
+    }
+    telemetry::init(&cfg.telemetry.service_name, &cfg.telemetry.exporter()?)?;
+    let result = server::run(cfg).await;
+    telemetry::shutdown();
+    result
 }
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{Mutex, RwLock, RwLockReadGuard};
+use crate::metrics;
+use crate::storage::Storage;
+use crate::translog::{encode_log_root, InclusionProof, Leaf, QueuedLeaf, SignedRoot, TransparencyLog};
+use verillm_shared_utils::merkle::{self, Hash, Subtrees};
+
+/// Durable, append-only record of each tree's leaf values, in index order.
+#[async_trait]
+pub trait LeafStore: Send + Sync {
+    async fn load(&self, log_id: i64) -> Result<Vec<Vec<u8>>>;
+    /// Persists leaves taking indices `start_index..`; returns once durable.
+    /// Appends to the same log are never made concurrently.
+    async fn append(&self, log_id: i64, start_index: i64, leaf_values: &[Vec<u8>]) -> Result<()>;
+}
+
+/// In-process RFC 6962 log for deployments without a Trillian server.
+/// Leaves are integrated as soon as they are appended, so every tree's
+/// latest root covers all of its leaves. Trees are loaded from the store
+/// on first use and then served from memory, and each is locked on its
+/// own so one log's writes never hold up another.
+pub struct MerkleLog {
+    store: Box<dyn LeafStore>,
+    trees: Mutex<HashMap<i64, Arc<TreeSlot>>>,
+}
+
+#[derive(Default)]
+struct TreeSlot {
+    // Held across the store write so indices are assigned in store order;
+    // readers only wait on `tree` while a written batch is added to it.
+    append: Mutex<()>,
+    // Empty until first loaded, and again after a failed write, since the
+    // store may then hold leaves this process never saw.
+    tree: RwLock<Option<Tree>>,
+}
+
+struct Tree {
+    values: Vec<Vec<u8>>,
+    hashes: Subtrees,
+    index: HashMap<Hash, i64>,
+    updated_at_nanos: u64,
+}
+
+impl Tree {
+    fn new(values: Vec<Vec<u8>>) -> Self {
+        let mut tree = Self { values: Vec::new(), hashes: Subtrees::default(), index: HashMap::new(), updated_at_nanos: now_nanos() };
+        for value in values {
+            tree.push(value);
+        }
+        tree
+    }
+
+    fn push(&mut self, value: Vec<u8>) {
+        let hash = merkle::leaf_hash(&value);
+        self.index.insert(hash, self.size());
+        self.hashes.push(hash);
+        self.values.push(value);
+    }
+
+    fn size(&self) -> i64 {
+        self.hashes.len() as i64
+    }
+
+    /// Checks `tree_size` is in range.
+    fn check_size(&self, tree_size: i64) -> Result<usize> {
+        if tree_size < 0 || tree_size > self.size() {
+            return Err(anyhow!("Tree size {} out of range; tree has {} leaves", tree_size, self.size()));
+        }
+        Ok(tree_size as usize)
+    }
+}
+
+impl MerkleLog {
+    pub fn new(store: Box<dyn LeafStore>) -> Self {
+        Self { store, trees: Mutex::new(HashMap::new()) }
+    }
+
+    /// Runs `f` on the tree, loading it from the store first if needed.
+    async fn with_tree<T>(&self, log_id: i64, f: impl FnOnce(&Tree) -> Result<T>) -> Result<T> {
+        let slot = self.slot(log_id).await;
+        let tree = self.tree(&slot, log_id).await?;
+        f(&tree)
+    }
+
+    async fn slot(&self, log_id: i64) -> Arc<TreeSlot> {
+        self.trees.lock().await.entry(log_id).or_default().clone()
+    }
+
+    async fn tree<'a>(&self, slot: &'a TreeSlot, log_id: i64) -> Result<RwLockReadGuard<'a, Tree>> {
+        loop {
+            if let Ok(tree) = RwLockReadGuard::try_map(slot.tree.read().await, Option::as_ref) {
+                return Ok(tree);
+            }
+            let mut tree = slot.tree.write().await;
+            if tree.is_none() {
+                *tree = Some(Tree::new(self.store.load(log_id).await?));
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl TransparencyLog for MerkleLog {
+    async fn append(&self, log_id: i64, leaf_values: &[Vec<u8>]) -> Result<Vec<Result<QueuedLeaf>>> {
+        let slot = self.slot(log_id).await;
+        let _appending = slot.append.lock().await;
+
+        let mut added: Vec<Vec<u8>> = Vec::new();
+        let (start, results) = {
+            let tree = self.tree(&slot, log_id).await?;
+            let start = tree.size();
+            let mut added_index: HashMap<Hash, i64> = HashMap::new();
+            let results: Vec<QueuedLeaf> = leaf_values.iter()
+                .map(|value| {
+                    let hash = merkle::leaf_hash(value);
+                    match tree.index.get(&hash).or_else(|| added_index.get(&hash)) {
+                        Some(index) => QueuedLeaf { leaf_index: *index, duplicate: true },
+                        None => {
+                            let index = start + added.len() as i64;
+                            added.push(value.clone());
+                            added_index.insert(hash, index);
+                            QueuedLeaf { leaf_index: index, duplicate: false }
+                        }
+                    }
+                })
+                .collect();
+            (start, results)
+        };
+
+        if !added.is_empty() {
+            if let Err(e) = self.store.append(log_id, start, &added).await {
+                // Typically another writer took these indices first; reload
+                // the tree before it's used again
+                *slot.tree.write().await = None;
+                return Err(e);
+            }
+            let mut tree = slot.tree.write().await;
+            let tree = tree.as_mut().expect("only a failed append unloads the tree");
+            for value in added {
+                tree.push(value);
+            }
+            tree.updated_at_nanos = now_nanos();
+        }
+        Ok(results.into_iter().map(Ok).collect())
+    }
+
+    async fn latest_root(&self, log_id: i64) -> Result<SignedRoot> {
+        self.with_tree(log_id, |tree| {
+            let root_hash = tree.hashes.root(tree.hashes.len()).to_vec();
+            let log_root = encode_log_root(tree.size(), &root_hash, tree.updated_at_nanos, tree.size() as u64);
+            metrics::TREE_SIZE.with_label_values(&[&log_id.to_string()]).set(tree.size());
+            Ok(SignedRoot {
+                root_hash,
+                tree_size: tree.size(),
+                timestamp_nanos: tree.updated_at_nanos,
+                log_root,
+            })
+        })
+        .await
+    }
+
+    async fn inclusion_proof(&self, log_id: i64, leaf_value: &[u8], tree_size: i64) -> Result<Option<InclusionProof>> {
+        let hash = merkle::leaf_hash(leaf_value);
+        self.with_tree(log_id, |tree| {
+            let size = tree.check_size(tree_size)?;
+            Ok(match tree.index.get(&hash) {
+                Some(index) if *index < tree_size => Some(InclusionProof {
+                    leaf_index: *index,
+                    hashes: to_vecs(tree.hashes.inclusion_path(*index as usize, size)),
+                }),
+                _ => None,
+            })
+        })
+        .await
+    }
+
+    async fn consistency_proof(&self, log_id: i64, first_tree_size: i64, second_tree_size: i64) -> Result<Vec<Vec<u8>>> {
+        self.with_tree(log_id, |tree| {
+            let size = tree.check_size(second_tree_size)?;
+            if first_tree_size < 0 || first_tree_size > second_tree_size {
+                return Err(anyhow!("Invalid consistency range {}..{}", first_tree_size, second_tree_size));
+            }
+            Ok(to_vecs(tree.hashes.consistency_proof(first_tree_size as usize, size)))
+        })
+        .await
+    }
+
+    async fn leaves_by_range(&self, log_id: i64, start_index: i64, count: i64) -> Result<Vec<Leaf>> {
+        self.with_tree(log_id, |tree| {
+            if start_index < 0 || count < 0 {
+                return Err(anyhow!("Invalid leaf range {}+{}", start_index, count));
+            }
+            let end = start_index.saturating_add(count).min(tree.size());
+            Ok((start_index..end)
+                .map(|index| Leaf {
+                    leaf_index: index,
+                    leaf_value: tree.values[index as usize].clone(),
+                    merkle_leaf_hash: tree.hashes.leaf(index as usize).to_vec(),
+                })
+                .collect())
+        })
+        .await
+    }
+}
+
+/// Keeps each tree in `<dir>/<log_id>.leaves` as length-prefixed leaf
+/// values (4-byte big-endian length, then the value).
+pub struct DiskLeafStore {
+    dir: PathBuf,
+    // Bytes of whole records in each loaded file; anything past this is a
+    // record torn by a crash mid-write, never acknowledged, and overwritten.
+    valid_len: Mutex<HashMap<i64, u64>>,
+}
+
+impl DiskLeafStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create log directory {}", dir.display()))?;
+        Ok(Self { dir, valid_len: Mutex::new(HashMap::new()) })
+    }
+
+    fn path(&self, log_id: i64) -> PathBuf {
+        self.dir.join(format!("{}.leaves", log_id))
+    }
+}
+
+#[async_trait]
+impl LeafStore for DiskLeafStore {
+    async fn load(&self, log_id: i64) -> Result<Vec<Vec<u8>>> {
+        let data = match tokio::fs::read(self.path(log_id)).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut values = Vec::new();
+        let mut offset = 0;
+        while data.len() - offset >= 4 {
+            let len = u32::from_be_bytes(data[offset..offset + 4].try_into()?) as usize;
+            if data.len() - offset - 4 < len {
+                break;
+            }
+            values.push(data[offset + 4..offset + 4 + len].to_vec());
+            offset += 4 + len;
+        }
+        self.valid_len.lock().await.insert(log_id, offset as u64);
+        Ok(values)
+    }
+
+    async fn append(&self, log_id: i64, start_index: i64, leaf_values: &[Vec<u8>]) -> Result<()> {
+        let len = *self.valid_len.lock().await.get(&log_id)
+            .ok_or_else(|| anyhow!("Log {} appended to before being loaded", log_id))?;
+        let mut buf = Vec::new();
+        for value in leaf_values {
+            buf.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            buf.extend_from_slice(value);
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(self.path(log_id))
+            .await?;
+        file.set_len(len).await?;
+        file.seek(std::io::SeekFrom::Start(len)).await?;
+        file.write_all(&buf).await?;
+        file.sync_data().await
+            .with_context(|| format!("Failed to sync leaves {}.. of log {}", start_index, log_id))?;
+        self.valid_len.lock().await.insert(log_id, len + buf.len() as u64);
+        Ok(())
+    }
+}
+
+/// Keeps trees in the auditor's database (`merkle_leaves`).
+pub struct DatabaseLeafStore {
+    storage: Arc<dyn Storage>,
+}
+
+impl DatabaseLeafStore {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage }
+    }
+}
+
+#[async_trait]
+impl LeafStore for DatabaseLeafStore {
+    async fn load(&self, log_id: i64) -> Result<Vec<Vec<u8>>> {
+        self.storage.merkle_leaves(log_id).await
+    }
+
+    async fn append(&self, log_id: i64, start_index: i64, leaf_values: &[Vec<u8>]) -> Result<()> {
+        self.storage.append_merkle_leaves(log_id, start_index, leaf_values).await
+    }
+}
+
+fn to_vecs(hashes: Vec<Hash>) -> Vec<Vec<u8>> {
+    hashes.into_iter().map(|hash| hash.to_vec()).collect()
+}
+
+fn now_nanos() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_disk_log_survives_reload() {
+        let dir = std::env::temp_dir().join(format!("verillm-merkle-log-{}", now_nanos()));
+        let values: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i; 32]).collect();
+
+        let log = MerkleLog::new(Box::new(DiskLeafStore::new(&dir).unwrap()));
+        let queued = log.append(7, &values[..3]).await.unwrap();
+        assert!(queued.iter().all(|leaf| !leaf.as_ref().unwrap().duplicate));
+        let queued = log.append(7, &[values[1].clone(), values[3].clone(), values[4].clone(), values[4].clone()]).await.unwrap();
+        let queued: Vec<QueuedLeaf> = queued.into_iter().map(Result::unwrap).collect();
+        assert!(queued[0].duplicate && queued[0].leaf_index == 1);
+        assert!(!queued[1].duplicate && queued[1].leaf_index == 3);
+        assert!(queued[3].duplicate && queued[3].leaf_index == 4);
+        let root = log.latest_root(7).await.unwrap();
+        assert_eq!(root.tree_size, 5);
+
+        let reloaded = MerkleLog::new(Box::new(DiskLeafStore::new(&dir).unwrap()));
+        assert_eq!(reloaded.latest_root(7).await.unwrap().root_hash, root.root_hash);
+        let proof = reloaded.inclusion_proof(7, &values[2], 5).await.unwrap().unwrap();
+        assert_eq!(proof.leaf_index, 2);
+        assert!(reloaded.inclusion_proof(7, &[9; 32], 5).await.unwrap().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Shared between logs like a database; rejects writes to taken indices.
+    #[derive(Clone, Default)]
+    struct SharedLeafStore {
+        trees: Arc<std::sync::Mutex<HashMap<i64, Vec<Vec<u8>>>>>,
+    }
+
+    #[async_trait]
+    impl LeafStore for SharedLeafStore {
+        async fn load(&self, log_id: i64) -> Result<Vec<Vec<u8>>> {
+            Ok(self.trees.lock().unwrap().get(&log_id).cloned().unwrap_or_default())
+        }
+
+        async fn append(&self, log_id: i64, start_index: i64, leaf_values: &[Vec<u8>]) -> Result<()> {
+            let mut trees = self.trees.lock().unwrap();
+            let tree = trees.entry(log_id).or_default();
+            if tree.len() as i64 != start_index {
+                return Err(anyhow!("Leaf {} of log {} already exists", start_index, log_id));
+            }
+            tree.extend_from_slice(leaf_values);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_log_reloads_after_another_writer_appends() {
+        let store = SharedLeafStore::default();
+        let first = MerkleLog::new(Box::new(store.clone()));
+        let second = MerkleLog::new(Box::new(store));
+        assert_eq!(second.latest_root(1).await.unwrap().tree_size, 0);
+
+        first.append(1, &[vec![0; 32]]).await.unwrap();
+        assert!(second.append(1, &[vec![1; 32]]).await.is_err());
+        let queued = second.append(1, &[vec![1; 32]]).await.unwrap();
+        assert_eq!(queued[0].as_ref().unwrap().leaf_index, 1);
+        let root = second.latest_root(1).await.unwrap();
+        assert_eq!(root.tree_size, 2);
+        let mut expected = Subtrees::default();
+        expected.push(merkle::leaf_hash(&[0; 32]));
+        expected.push(merkle::leaf_hash(&[1; 32]));
+        assert_eq!(root.root_hash, expected.root(2).to_vec());
+    }
+}
@@ -0,0 +1,149 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Instant;
+use axum::{http::header, routing::get, Router};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec, Encoder, Histogram, HistogramVec,
+    IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use tracing::{error, info, info_span, Instrument};
+
+/// Submissions by outcome: `accepted`, `duplicate`, `rejected` (identity or
+/// rate limit) or `failed`.
+pub static SUBMISSIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "auditor_submissions_total",
+        "Hash submissions answered, by outcome",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+pub static BATCH_SIZE: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "auditor_batch_size",
+        "Submissions per flushed batch",
+        vec![1.0, 5.0, 10.0, 25.0, 50.0, 75.0, 100.0]
+    )
+    .unwrap()
+});
+
+pub static BATCH_FLUSH_SECONDS: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "auditor_batch_flush_seconds",
+        "Time to log a batch and answer its submissions"
+    )
+    .unwrap()
+});
+
+pub static QUEUE_DEPTH: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "auditor_queue_depth",
+        "Submissions waiting in the batch queue"
+    )
+    .unwrap()
+});
+
+pub static TREE_SIZE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "auditor_tree_size",
+        "Size of the latest signed root seen for each log",
+        &["log_id"]
+    )
+    .unwrap()
+});
+
+static RETRIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "auditor_retries_total",
+        "Retried attempts at a submission processing stage",
+        &["stage"]
+    )
+    .unwrap()
+});
+
+static DEAD_LETTERS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "auditor_dead_letters_total",
+        "Submissions given up on, by the stage that failed",
+        &["stage"]
+    )
+    .unwrap()
+});
+
+static DEPENDENCY_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "auditor_dependency_call_seconds",
+        "Latency of calls to the log server, Postgres and Kafka",
+        &["dependency", "operation"]
+    )
+    .unwrap()
+});
+
+static DEPENDENCY_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "auditor_dependency_errors_total",
+        "Failed calls to the log server, Postgres and Kafka",
+        &["dependency", "operation"]
+    )
+    .unwrap()
+});
+
+/// Runs a call to one of the auditor's dependencies, recording its latency
+/// and whether it failed, in a span of its own.
+pub async fn timed<T, E, F>(dependency: &str, operation: &str, call: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    let span = info_span!(
+        "dependency",
+        otel.name = %format!("{} {}", dependency, operation),
+        dependency,
+        operation,
+    );
+    let start = Instant::now();
+    let result = call.instrument(span).await;
+    DEPENDENCY_SECONDS
+        .with_label_values(&[dependency, operation])
+        .observe(start.elapsed().as_secs_f64());
+    if result.is_err() {
+        DEPENDENCY_ERRORS.with_label_values(&[dependency, operation]).inc();
+    }
+    result
+}
+
+pub fn record_submission(outcome: &str) {
+    SUBMISSIONS.with_label_values(&[outcome]).inc();
+}
+
+pub fn record_retry(stage: &str) {
+    RETRIES.with_label_values(&[stage]).inc();
+}
+
+pub fn record_dead_letter(stage: &str) {
+    DEAD_LETTERS.with_label_values(&[stage]).inc();
+}
+
+/// Serves the default registry in the Prometheus text format on `/metrics`
+/// until shutdown is requested.
+pub async fn serve(addr: SocketAddr, shutdown: impl Future<Output = ()>) {
+    let app = Router::new().route("/metrics", get(render));
+    info!("Serving metrics on {}", addr);
+    let server = axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown);
+    if let Err(e) = server.await {
+        error!("Metrics server failed: {}", e);
+    }
+}
+
+async fn render() -> ([(header::HeaderName, String); 1], Vec<u8>) {
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    if let Err(e) = encoder.encode(&prometheus::gather(), &mut body) {
+        error!("Failed to encode metrics: {}", e);
+    }
+    ([(header::CONTENT_TYPE, encoder.format_type().to_string())], body)
+}
@@ -0,0 +1,183 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use anyhow::Result;
+use chrono::Utc;
+use tokio::time::{self, Duration, Instant};
+use tracing::{debug, error, warn};
+use crate::config::OutboxConfig;
+use crate::events::{EventSink, EventSinks, ReceiptEvent};
+use crate::storage::Storage;
+
+/// How often delivered events past the retention are deleted.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Publishes events queued in the outbox to each sink and records which
+/// sinks have them; an event is delivered once all of them do. Delivery is
+/// at least once: an event is re-sent to a sink if the relay stops between
+/// publishing it and recording that. Within a log, each sink gets events in
+/// leaf order; a failure holds back that log's later events for that sink
+/// until it succeeds, while the other sinks carry on.
+pub struct OutboxRelay {
+    storage: Arc<dyn Storage>,
+    events: Arc<EventSinks>,
+    cfg: OutboxConfig,
+}
+
+impl OutboxRelay {
+    pub fn new(storage: Arc<dyn Storage>, events: Arc<EventSinks>, cfg: OutboxConfig) -> Self {
+        Self { storage, events, cfg }
+    }
+
+    pub async fn run(self) {
+        let mut interval = time::interval(Duration::from_millis(self.cfg.poll_interval_ms));
+        let mut next_prune = Instant::now();
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.relay().await {
+                error!("Failed to relay outbox events: {}", e);
+            }
+            if Instant::now() >= next_prune {
+                next_prune = Instant::now() + PRUNE_INTERVAL;
+                if let Err(e) = self.prune().await {
+                    error!("Failed to prune delivered outbox events: {}", e);
+                }
+            }
+        }
+    }
+
+    async fn relay(&self) -> Result<()> {
+        let sinks = self.events.sinks();
+        let results = futures::future::join_all(sinks.iter().map(|sink| self.relay_to(sink.as_ref()))).await;
+        for (sink, result) in sinks.iter().zip(results) {
+            if let Err(e) = result {
+                error!("Failed to relay outbox events to {}: {}", sink.name(), e);
+            }
+        }
+        let names: Vec<&str> = sinks.iter().map(|sink| sink.name()).collect();
+        self.storage.complete_deliveries(&names).await
+    }
+
+    async fn relay_to(&self, sink: &dyn EventSink) -> Result<()> {
+        let pending = self.storage.undelivered_events(sink.name(), self.cfg.batch_size).await?;
+        let mut delivered = Vec::new();
+        let mut blocked = HashSet::new();
+        for event in pending {
+            if blocked.contains(&event.log_id) {
+                continue;
+            }
+            let receipt_event = ReceiptEvent {
+                tenant_id: event.tenant_id,
+                log_id: event.log_id,
+                leaf_hash: event.leaf_hash,
+                leaf_index: event.leaf_index,
+                receipt: event.receipt_jwt,
+            };
+            match sink.publish(&receipt_event).await {
+                Ok(()) => delivered.push(event.id),
+                Err(e) => {
+                    warn!(
+                        "Failed to publish receipt {} (log {}, index {}) to {}, will retry: {}",
+                        hex::encode(&receipt_event.leaf_hash),
+                        event.log_id,
+                        event.leaf_index,
+                        sink.name(),
+                        e
+                    );
+                    blocked.insert(event.log_id);
+                }
+            }
+        }
+        if delivered.is_empty() {
+            return Ok(());
+        }
+        self.storage.mark_delivered(sink.name(), &delivered).await
+    }
+
+    async fn prune(&self) -> Result<()> {
+        let before = Utc::now() - chrono::Duration::seconds(self.cfg.retention_secs as i64);
+        let pruned = self.storage.prune_delivered_events(before).await?;
+        if pruned > 0 {
+            debug!("Pruned {} delivered outbox events", pruned);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use async_trait::async_trait;
+    use super::*;
+    use crate::config::DatabaseConfig;
+    use crate::storage::SqliteStorage;
+
+    type Published = Arc<Mutex<Vec<(i64, i64)>>>;
+    type FailAt = Arc<Mutex<Option<(i64, i64)>>>;
+
+    /// Records the (log, index) of what it publishes; fails for the event
+    /// at `fail_at` while set.
+    struct RecordingSink {
+        name: &'static str,
+        published: Published,
+        fail_at: FailAt,
+    }
+
+    #[async_trait]
+    impl EventSink for RecordingSink {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn publish(&self, event: &ReceiptEvent) -> Result<()> {
+            if *self.fail_at.lock().unwrap() == Some((event.log_id, event.leaf_index)) {
+                anyhow::bail!("unavailable");
+            }
+            self.published.lock().unwrap().push((event.log_id, event.leaf_index));
+            Ok(())
+        }
+    }
+
+    fn sink(name: &'static str) -> (RecordingSink, Published, FailAt) {
+        let published = Arc::new(Mutex::new(Vec::new()));
+        let fail_at = Arc::new(Mutex::new(None));
+        (RecordingSink { name, published: published.clone(), fail_at: fail_at.clone() }, published, fail_at)
+    }
+
+    fn take(published: &Mutex<Vec<(i64, i64)>>) -> Vec<(i64, i64)> {
+        std::mem::take(&mut *published.lock().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_failed_sink_is_redelivered_alone_and_in_order() {
+        let storage: Arc<dyn Storage> = Arc::new(
+            SqliteStorage::new(&DatabaseConfig { url: "sqlite::memory:".to_string(), password: None }).await.unwrap(),
+        );
+        let events = [(1, 0), (2, 0), (1, 1), (1, 2)];
+        for (i, (log_id, leaf_index)) in events.iter().enumerate() {
+            let leaf_hash = [i as u8];
+            storage.store_pending_receipt("tenant", *log_id, "proxy", &leaf_hash, b"{}", "promise").await.unwrap();
+            storage.mark_integrated("tenant", *log_id, &leaf_hash, *leaf_index, &[0; 32], "receipt").await.unwrap();
+        }
+        let (kafka, kafka_published, _) = sink("kafka");
+        let (webhook, webhook_published, webhook_fail_at) = sink("webhook");
+        *webhook_fail_at.lock().unwrap() = Some((1, 1));
+        let cfg = OutboxConfig { batch_size: 2, ..OutboxConfig::default() };
+        let relay = OutboxRelay::new(storage.clone(), Arc::new(EventSinks::from_sinks(vec![Box::new(kafka), Box::new(webhook)])), cfg);
+
+        relay.relay().await.unwrap();
+        assert_eq!(take(&kafka_published), vec![(1, 0), (1, 1), (2, 0)]);
+        // The failure holds back the rest of log 1 for the webhook only
+        assert_eq!(take(&webhook_published), vec![(1, 0), (2, 0)]);
+
+        *webhook_fail_at.lock().unwrap() = None;
+        relay.relay().await.unwrap();
+        assert_eq!(take(&kafka_published), vec![(1, 2)]);
+        assert_eq!(take(&webhook_published), vec![(1, 1), (1, 2)]);
+
+        relay.relay().await.unwrap();
+        assert!(take(&kafka_published).is_empty() && take(&webhook_published).is_empty());
+        assert!(storage.undelivered_events("kafka", 10).await.unwrap().is_empty());
+        let pruned = storage.prune_delivered_events(Utc::now() + chrono::Duration::seconds(1)).await.unwrap();
+        assert_eq!(pruned, 4);
+    }
+}
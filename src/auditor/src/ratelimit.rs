@@ -0,0 +1,48 @@
+use std::num::NonZeroU32;
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
+use tonic::Status;
+use crate::config::LimitsConfig;
+
+/// Per-caller submission rate limits, keyed by the `proxy_id` a submission
+/// arrives with and by the tenant that sent it.
+pub struct RateLimits {
+    per_proxy: Option<DefaultKeyedRateLimiter<String>>,
+    per_tenant: Option<DefaultKeyedRateLimiter<String>>,
+}
+
+impl RateLimits {
+    pub fn new(cfg: &LimitsConfig) -> Self {
+        Self {
+            per_proxy: keyed_limiter(cfg.per_proxy_per_second, cfg.per_proxy_burst),
+            per_tenant: keyed_limiter(cfg.per_tenant_per_second, cfg.per_tenant_burst),
+        }
+    }
+
+    pub fn check(&self, tenant_id: &str, proxy_id: &str) -> Result<(), Status> {
+        if let Some(limiter) = &self.per_tenant {
+            if limiter.check_key(&tenant_id.to_string()).is_err() {
+                return Err(Status::resource_exhausted(format!(
+                    "Submission rate limit exceeded for tenant {}",
+                    tenant_id
+                )));
+            }
+        }
+        if let Some(limiter) = &self.per_proxy {
+            if limiter.check_key(&proxy_id.to_string()).is_err() {
+                return Err(Status::resource_exhausted(format!(
+                    "Submission rate limit exceeded for proxy {}",
+                    proxy_id
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds a keyed limiter, or `None` when the rate is 0 (unlimited). A burst
+/// of 0 defaults to one second's worth of submissions.
+fn keyed_limiter(per_second: u32, burst: u32) -> Option<DefaultKeyedRateLimiter<String>> {
+    let per_second = NonZeroU32::new(per_second)?;
+    let burst = NonZeroU32::new(burst).unwrap_or(per_second);
+    Some(RateLimiter::keyed(Quota::per_second(per_second).allow_burst(burst)))
+}
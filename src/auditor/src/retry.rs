@@ -0,0 +1,125 @@
+//! Retries for the stages a submission passes through, with exponential
+//! backoff and full jitter. Only errors that may succeed on a later attempt
+//! are retried; anything else fails at once.
+
+use std::future::Future;
+use std::io::ErrorKind;
+use anyhow::Result;
+use rand::Rng;
+use tokio::time::{self, Duration};
+use tonic::Code;
+use tracing::warn;
+use crate::config::RetryConfig;
+use crate::metrics;
+
+/// Runs `attempt` until it succeeds, fails with a permanent error, or has
+/// been tried `max_attempts` times. Returns the last error along with the
+/// number of attempts made.
+pub async fn retry<T, F, Fut>(cfg: &RetryConfig, stage: &str, mut attempt: F) -> Result<T, (anyhow::Error, u32)>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempts < cfg.max_attempts && is_retryable(&e) => {
+                let delay = backoff(cfg, attempts);
+                warn!("{} failed (attempt {}), retrying in {:?}: {}", stage, attempts, delay, e);
+                metrics::record_retry(stage);
+                time::sleep(delay).await;
+            }
+            Err(e) => return Err((e, attempts)),
+        }
+    }
+}
+
+/// Full jitter: uniform between zero and the exponential backoff for this
+/// attempt, capped at `max_backoff_ms`.
+fn backoff(cfg: &RetryConfig, attempts: u32) -> Duration {
+    let exponential = cfg.initial_backoff_ms.saturating_mul(1u64 << (attempts - 1).min(32));
+    let cap = exponential.min(cfg.max_backoff_ms);
+    Duration::from_millis(rand::thread_rng().gen_range(0..=cap))
+}
+
+/// Whether an error is transient: unreachable or overloaded dependencies,
+/// timeouts and lost connections. Rejections, malformed data and bugs are
+/// permanent, as is anything not recognised.
+pub fn is_retryable(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        if let Some(status) = cause.downcast_ref::<tonic::Status>() {
+            return matches!(
+                status.code(),
+                Code::Unavailable | Code::DeadlineExceeded | Code::ResourceExhausted | Code::Aborted
+            );
+        }
+        if let Some(e) = cause.downcast_ref::<sqlx::Error>() {
+            return match e {
+                sqlx::Error::Io(e) => is_transient_io(e),
+                sqlx::Error::PoolTimedOut | sqlx::Error::WorkerCrashed => true,
+                // Serialization failure, deadlock, too many connections
+                sqlx::Error::Database(db) => matches!(db.code().as_deref(), Some("40001" | "40P01" | "53300")),
+                _ => false,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return is_transient_io(e);
+        }
+        cause.is::<tonic::transport::Error>()
+    })
+}
+
+/// Lost or refused connections and timeouts; not missing files, denied
+/// permissions or bad data.
+fn is_transient_io(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::TimedOut
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::UnexpectedEof
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_retryable_classifies_status_codes() {
+        assert!(is_retryable(&tonic::Status::unavailable("down").into()));
+        assert!(is_retryable(&anyhow::Error::from(tonic::Status::deadline_exceeded("slow")).context("append")));
+        assert!(!is_retryable(&tonic::Status::invalid_argument("bad leaf").into()));
+        assert!(!is_retryable(&anyhow::anyhow!("Receipt conflict but no existing receipt")));
+    }
+
+    #[test]
+    fn test_is_retryable_classifies_io_errors_by_kind() {
+        let io = |kind| anyhow::Error::from(std::io::Error::from(kind));
+        assert!(is_retryable(&io(ErrorKind::ConnectionReset)));
+        assert!(is_retryable(&io(ErrorKind::TimedOut).context("sync leaves")));
+        assert!(is_retryable(&sqlx::Error::Io(std::io::Error::from(ErrorKind::BrokenPipe)).into()));
+        assert!(!is_retryable(&io(ErrorKind::PermissionDenied)));
+        assert!(!is_retryable(&io(ErrorKind::NotFound)));
+        assert!(!is_retryable(&io(ErrorKind::InvalidData)));
+    }
+
+    #[test]
+    fn test_backoff_stays_under_cap() {
+        let cfg = RetryConfig {
+            max_attempts: 10,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1000,
+        };
+        for attempts in 1..=40 {
+            assert!(backoff(&cfg, attempts) <= Duration::from_millis(1000));
+        }
+    }
+}
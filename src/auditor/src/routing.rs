@@ -0,0 +1,36 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use anyhow::Result;
+use crate::config::TrillianConfig;
+use crate::storage::Storage;
+
+/// Decides which log tree a tenant's leaves are written to: the
+/// tenant's entry in config, else its row in `tenant_logs`, else the
+/// default tree.
+pub struct LogRouter {
+    default_log_id: i64,
+    configured: HashMap<String, i64>,
+    storage: Arc<dyn Storage>,
+}
+
+impl LogRouter {
+    pub fn new(cfg: &TrillianConfig, storage: Arc<dyn Storage>) -> Self {
+        Self {
+            default_log_id: cfg.log_id,
+            configured: cfg.tenant_logs.clone(),
+            storage,
+        }
+    }
+
+    pub fn default_log_id(&self) -> i64 {
+        self.default_log_id
+    }
+
+    pub async fn log_id(&self, tenant_id: &str) -> Result<i64> {
+        if let Some(log_id) = self.configured.get(tenant_id) {
+            return Ok(*log_id);
+        }
+        Ok(self.storage.tenant_log_id(tenant_id).await?
+            .unwrap_or(self.default_log_id))
+    }
+}
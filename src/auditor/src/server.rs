@@ -1,105 +1,508 @@
 use tonic::{Request, Response, Status, Streaming};
+use crate::admin::AdminService;
 use crate::auditor::{
+    auditor_admin_server::AuditorAdminServer,
     auditor_server::{Auditor, AuditorServer},
-    HashSubmission, ReceiptResponse, ReceiptRequest,
+    Checkpoint, CheckpointRequest, ConsistencyProofRequest, ConsistencyProofResponse,
+    HashSubmission, InclusionProofRequest, InclusionProofResponse, LeavesByRangeRequest,
+    LeavesByRangeResponse, ListReceiptsRequest, ListReceiptsResponse, LogEntry,
+    ReceiptFormat, ReceiptResponse, ReceiptRequest, ReceiptState, Revocation, RevokeReceiptRequest,
 };
-use crate::config::Config;
-use crate::storage::Storage;
+use crate::auth::{self, ApiKeyAuth};
+use crate::config::{Config, LeafStoreKind, LogBackend, RetryConfig};
+use crate::retry::{self, retry};
+use crate::gateway;
+use crate::health;
+use crate::integrator::{Integrator, ReceiptUpdate};
+use crate::ratelimit::RateLimits;
+use crate::routing::LogRouter;
+use crate::tls::{self, PeerIdentity};
+use crate::storage::{
+    self, DeadLetter, ReceiptCursor, ReceiptFilter, ReceiptRecord, RevocationRecord, Storage, STATUS_PENDING,
+};
+use crate::merkle_log::{DiskLeafStore, LeafStore, MerkleLog, DatabaseLeafStore};
+use crate::translog::{QueuedLeaf, TransparencyLog};
 use crate::trillian::TrillianClient;
 use crate::signer::Signer;
-use crate::kafka::KafkaProducer;
+use verillm_shared_utils::telemetry;
+use crate::events::EventSinks;
+use crate::outbox::OutboxRelay;
+use crate::metrics;
+use crate::google::rpc::Status as RpcStatus;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL;
+use base64::Engine;
 use futures::StreamExt;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::mpsc::{self, UnboundedSender, UnboundedReceiver};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
 use tokio::time::{self, Duration};
-use tracing::{info, error};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{info, error, info_span, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use opentelemetry::trace::TraceContextExt;
+
+/// Submissions a single `SubmitHash` stream may have in the batch queue at
+/// once. Also the capacity of its result channel, so the batch worker never
+/// blocks on a slow reader.
+const MAX_IN_FLIGHT_PER_STREAM: usize = 128;
+
+/// Batches processed at once. Each runs on its own task, so one waiting out
+/// retry backoff doesn't hold up the batches queued behind it.
+const MAX_BATCHES_IN_FLIGHT: usize = 4;
+
+/// Page size bounds for `ListReceipts`.
+const DEFAULT_PAGE_SIZE: u32 = 100;
+const MAX_PAGE_SIZE: u32 = 1000;
+
+/// Most leaves returned by one `GetLeavesByRange` call.
+const MAX_LEAVES_PER_RANGE: u64 = 1000;
+
+/// A submission waiting in the batching channel, together with the
+/// `SubmitHash` call it arrived on.
+struct PendingSubmission {
+    tenant_id: String,
+    submission: HashSubmission,
+    respond_to: mpsc::Sender<ReceiptResponse>,
+    /// Span covering the submission until it is answered.
+    span: Span,
+}
 
 pub struct AuditorService {
-    storage: Arc<Storage>,
-    trillian: Arc<TrillianClient>,
+    storage: Arc<dyn Storage>,
+    log: Arc<dyn TransparencyLog>,
+    router: Arc<LogRouter>,
     signer: Arc<Signer>,
-    kafka: Arc<KafkaProducer>,
-    batch_tx: UnboundedSender<HashSubmission>,
+    batch_tx: mpsc::Sender<PendingSubmission>,
+    updates: broadcast::Sender<ReceiptUpdate>,
+    rate_limits: Arc<RateLimits>,
+    // Set when mutual TLS is configured
+    require_client_identity: bool,
 }
 
 #[tonic::async_trait]
 impl Auditor for AuditorService {
-    type SubmitHashStream = mpsc::Receiver<Result<ReceiptResponse, Status>>;
+    type SubmitHashStream = ReceiverStream<Result<ReceiptResponse, Status>>;
 
     async fn submit_hash(
         &self,
         request: Request<Streaming<HashSubmission>>,
     ) -> Result<Response<Self::SubmitHashStream>, Status> {
+        let tenant_id = auth::tenant_of(&request)?;
+        let identity = PeerIdentity::from_request(&request)?;
+        if self.require_client_identity && identity.is_none() {
+            return Err(Status::unauthenticated("Client certificate required"));
+        }
+
         let mut stream = request.into_inner();
         let (tx, rx) = mpsc::channel(128);
+        // The batch worker answers here; the task below forwards to `tx`
+        let (batch_results_tx, mut batch_results) = mpsc::channel(MAX_IN_FLIGHT_PER_STREAM);
 
         let batch_tx = self.batch_tx.clone();
-        let this = self.clone(); // clone Arcs for the spawned task
+        let mut updates = self.updates.subscribe();
+        let rate_limits = self.rate_limits.clone();
 
         tokio::spawn(async move {
-            while let Some(submission) = stream.next().await {
-                match submission {
-                    Ok(sub) => {
-                        // Send to batching channel
-                        if batch_tx.send(sub).is_err() {
-                            error!("Batching channel closed");
-                            break;
+            // Leaves answered with a promise whose integrated receipt this
+            // stream is still waiting for.
+            let mut awaiting: HashSet<Vec<u8>> = HashSet::new();
+            let mut input_open = true;
+            let mut in_flight = 0usize;
+
+            loop {
+                let response = tokio::select! {
+                    // The stream is only read while this caller has room in
+                    // flight, and sending waits while the batch queue is full,
+                    // so a busy auditor slows producers down via flow control.
+                    submission = stream.next(), if input_open && in_flight < MAX_IN_FLIGHT_PER_STREAM => {
+                        match submission {
+                            Some(Ok(sub)) => {
+                                let admitted = match &identity {
+                                    Some(identity) => identity.check(&sub.proxy_id),
+                                    None => Ok(()),
+                                }
+                                .and_then(|_| rate_limits.check(&tenant_id, &sub.proxy_id));
+                                if let Err(status) = admitted {
+                                    metrics::record_submission("rejected");
+                                    Some(error_response(sub.hash, status))
+                                } else {
+                                    let pending = PendingSubmission {
+                                        tenant_id: tenant_id.clone(),
+                                        span: submission_span(&sub),
+                                        submission: sub,
+                                        respond_to: batch_results_tx.clone(),
+                                    };
+                                    match batch_tx.send(pending).await {
+                                        Ok(()) => {
+                                            in_flight += 1;
+                                            None
+                                        }
+                                        Err(mpsc::error::SendError(pending)) => {
+                                            // The queue is closed while shutting down
+                                            input_open = false;
+                                            Some(error_response(pending.submission.hash, shutting_down()))
+                                        }
+                                    }
+                                }
+                            }
+                            Some(Err(e)) => {
+                                error!("Error in submission stream: {}", e);
+                                input_open = false;
+                                None
+                            }
+                            None => {
+                                input_open = false;
+                                None
+                            }
+                        }
+                    }
+                    Some(response) = batch_results.recv(), if in_flight > 0 => {
+                        in_flight -= 1;
+                        if response.state == ReceiptState::Pending as i32 {
+                            awaiting.insert(response.leaf_hash.clone());
+                        }
+                        Some(response)
+                    }
+                    update = updates.recv(), if !awaiting.is_empty() => {
+                        match update {
+                            Ok(update) if update.tenant_id == tenant_id
+                                && awaiting.remove(&update.response.leaf_hash) => Some(update.response),
+                            Ok(_) => None,
+                            Err(RecvError::Lagged(skipped)) => {
+                                // GetReceipt and the event sinks still carry the upgraded receipts
+                                error!("Receipt stream lagged, {} integration updates dropped", skipped);
+                                None
+                            }
+                            Err(RecvError::Closed) => break,
                         }
                     }
-                    Err(e) => {
-                        error!("Error in submission stream: {}", e);
+                    else => break,
+                };
+
+                if let Some(response) = response {
+                    if tx.send(Ok(response)).await.is_err() {
+                        // Caller hung up; receipts are stored either way
                         break;
                     }
                 }
+                if !input_open && in_flight == 0 && awaiting.is_empty() {
+                    break;
+                }
             }
-        });
+        }.instrument(Span::current()));
 
-        Ok(Response::new(mpsc::ReceiverStream::new(rx)))
+        Ok(Response::new(ReceiverStream::new(rx)))
     }
 
     async fn get_receipt(
         &self,
         request: Request<ReceiptRequest>,
     ) -> Result<Response<ReceiptResponse>, Status> {
-        let leaf_hash = request.into_inner().leaf_hash;
-        let receipt = self.storage.get_receipt(&leaf_hash).await
+        let tenant_id = auth::tenant_of(&request)?;
+        let req = request.into_inner();
+        let leaf_hash = req.leaf_hash;
+        let receipt = self.storage.get_receipt(&tenant_id, &leaf_hash).await
+            .map_err(|e| Status::internal(format!("Storage error: {}", e)))?
+            .ok_or_else(|| Status::not_found("Receipt not found"))?;
+        let revocation = self.storage.get_revocation(&tenant_id, &leaf_hash).await
             .map_err(|e| Status::internal(format!("Storage error: {}", e)))?;
-        Ok(Response::new(ReceiptResponse {
-            receipt: receipt.receipt_jwt.into_bytes(),
-            leaf_index: receipt.leaf_index as u64,
+        let mut response = ReceiptResponse {
+            revocation: revocation.as_ref().map(revocation_message),
+            ..receipt_response(&receipt)
+        };
+        match ReceiptFormat::try_from(req.format) {
+            Ok(ReceiptFormat::Unspecified | ReceiptFormat::Jws) => {}
+            Ok(ReceiptFormat::Cose) => response.receipt = self.cose_receipt(&receipt).await?,
+            Err(_) => return Err(Status::invalid_argument(format!("Unknown receipt format {}", req.format))),
+        }
+        Ok(Response::new(response))
+    }
+
+    async fn revoke_receipt(
+        &self,
+        request: Request<RevokeReceiptRequest>,
+    ) -> Result<Response<Revocation>, Status> {
+        let tenant_id = auth::tenant_of(&request)?;
+        let req = request.into_inner();
+        if req.reason.trim().is_empty() {
+            return Err(Status::invalid_argument("reason is required"));
+        }
+        let actor = if req.actor.is_empty() { tenant_id.clone() } else { req.actor };
+
+        let receipt = self.storage.get_receipt(&tenant_id, &req.leaf_hash).await
+            .map_err(|e| Status::internal(format!("Storage error: {}", e)))?
+            .ok_or_else(|| Status::not_found("Receipt not found"))?;
+        let existing = self.storage.get_revocation(&tenant_id, &req.leaf_hash).await
+            .map_err(|e| Status::internal(format!("Storage error: {}", e)))?;
+        if existing.is_some() {
+            return Err(Status::already_exists("Receipt is already revoked"));
+        }
+
+        // The statement goes into the same tree as the leaf it withdraws
+        let log_id = receipt.log_id.unwrap_or(self.router.default_log_id());
+        let revoked_at = chrono::Utc::now();
+        let statement = self.signer.sign_revocation(log_id, &req.leaf_hash, &req.reason, &actor, revoked_at).await
+            .map_err(|e| Status::internal(format!("Signing error: {}", e)))?;
+        let revocation_leaf_hash = blake3::hash(statement.as_bytes()).as_bytes().to_vec();
+        let appended = self.log.append(log_id, std::slice::from_ref(&revocation_leaf_hash)).await
+            .map_err(log_error)?;
+        for result in appended {
+            result.map_err(log_error)?;
+        }
+
+        let record = RevocationRecord {
+            tenant_id,
+            leaf_hash: req.leaf_hash,
+            log_id,
+            reason: req.reason,
+            actor,
+            statement,
+            revocation_leaf_hash,
+            revoked_at,
+        };
+        let stored = self.storage.store_revocation(&record).await
+            .map_err(|e| Status::internal(format!("Storage error: {}", e)))?;
+        if !stored {
+            // A concurrent call won; its statement is the one on record
+            return Err(Status::already_exists("Receipt is already revoked"));
+        }
+        info!("Receipt {} revoked by {}: {}", hex::encode(&record.leaf_hash), record.actor, record.reason);
+        Ok(Response::new(revocation_message(&record)))
+    }
+
+    async fn list_receipts(
+        &self,
+        request: Request<ListReceiptsRequest>,
+    ) -> Result<Response<ListReceiptsResponse>, Status> {
+        let tenant_id = auth::tenant_of(&request)?;
+        let req = request.into_inner();
+        let filter = ReceiptFilter {
+            created_after: timestamp_arg(req.created_after_ns)?,
+            created_before: timestamp_arg(req.created_before_ns)?,
+            model_id: Some(req.model_id).filter(|id| !id.is_empty()),
+            proxy_id: Some(req.proxy_id).filter(|id| !id.is_empty()),
+            context: context_filter(&req.context),
+        };
+        let cursor = match req.page_token.as_str() {
+            "" => None,
+            token => Some(decode_page_token(token)?),
+        };
+        let page_size = match req.page_size {
+            0 => DEFAULT_PAGE_SIZE,
+            size => size.min(MAX_PAGE_SIZE),
+        } as usize;
+
+        // One extra row tells whether another page follows
+        let mut records = self.storage.list_receipts(&tenant_id, &filter, cursor.as_ref(), page_size as i64 + 1).await
+            .map_err(|e| Status::internal(format!("Storage error: {}", e)))?;
+        let next_page_token = if records.len() > page_size {
+            records.truncate(page_size);
+            records.last().map(encode_page_token).unwrap_or_default()
+        } else {
+            String::new()
+        };
+        Ok(Response::new(ListReceiptsResponse {
+            receipts: records.iter().map(receipt_response).collect(),
+            next_page_token,
+        }))
+    }
+
+    async fn get_latest_checkpoint(
+        &self,
+        request: Request<CheckpointRequest>,
+    ) -> Result<Response<Checkpoint>, Status> {
+        let log_id = self.tenant_log(&request).await?;
+        let root = self.log.latest_root(log_id).await
+            .map_err(log_error)?;
+        Ok(Response::new(Checkpoint {
+            log_id,
+            tree_size: root.tree_size as u64,
+            root_hash: root.root_hash,
+            timestamp_nanos: root.timestamp_nanos,
+            log_root: root.log_root,
+        }))
+    }
+
+    async fn get_inclusion_proof(
+        &self,
+        request: Request<InclusionProofRequest>,
+    ) -> Result<Response<InclusionProofResponse>, Status> {
+        let log_id = self.tenant_log(&request).await?;
+        let req = request.into_inner();
+        let tree_size = match req.tree_size {
+            0 => self.log.latest_root(log_id).await.map_err(log_error)?.tree_size,
+            size => tree_size_arg(size)?,
+        };
+        let proof = self.log.inclusion_proof(log_id, &req.leaf_hash, tree_size).await
+            .map_err(log_error)?
+            .ok_or_else(|| Status::not_found("Leaf not included in the requested tree size"))?;
+        Ok(Response::new(InclusionProofResponse {
+            log_id,
+            leaf_index: proof.leaf_index as u64,
+            tree_size: tree_size as u64,
+            hashes: proof.hashes,
+        }))
+    }
+
+    async fn get_consistency_proof(
+        &self,
+        request: Request<ConsistencyProofRequest>,
+    ) -> Result<Response<ConsistencyProofResponse>, Status> {
+        let log_id = self.tenant_log(&request).await?;
+        let req = request.into_inner();
+        if req.first_tree_size == 0 || req.first_tree_size > req.second_tree_size {
+            return Err(Status::invalid_argument("Require 0 < first_tree_size <= second_tree_size"));
+        }
+        let hashes = self.log.consistency_proof(
+            log_id,
+            tree_size_arg(req.first_tree_size)?,
+            tree_size_arg(req.second_tree_size)?,
+        ).await.map_err(log_error)?;
+        Ok(Response::new(ConsistencyProofResponse {
+            log_id,
+            first_tree_size: req.first_tree_size,
+            second_tree_size: req.second_tree_size,
+            hashes,
+        }))
+    }
+
+    async fn get_leaves_by_range(
+        &self,
+        request: Request<LeavesByRangeRequest>,
+    ) -> Result<Response<LeavesByRangeResponse>, Status> {
+        let log_id = self.tenant_log(&request).await?;
+        let req = request.into_inner();
+        if req.count == 0 {
+            return Err(Status::invalid_argument("count must be positive"));
+        }
+        let count = req.count.min(MAX_LEAVES_PER_RANGE) as i64;
+        let leaves = self.log.leaves_by_range(log_id, tree_size_arg(req.start_index)?, count).await
+            .map_err(log_error)?;
+        Ok(Response::new(LeavesByRangeResponse {
+            log_id,
+            leaves: leaves.into_iter()
+                .map(|leaf| LogEntry {
+                    leaf_index: leaf.leaf_index as u64,
+                    leaf_value: leaf.leaf_value,
+                    merkle_leaf_hash: leaf.merkle_leaf_hash,
+                })
+                .collect(),
         }))
     }
 }
 
 impl AuditorService {
-    async fn process_single_submission(
+    /// Signs a COSE receipt for an integrated leaf against the latest root
+    /// of its tree.
+    async fn cose_receipt(&self, record: &ReceiptRecord) -> Result<Vec<u8>, Status> {
+        if record.status == STATUS_PENDING {
+            return Err(Status::failed_precondition("COSE receipts are issued once the leaf is integrated"));
+        }
+        let log_id = record.log_id.unwrap_or(self.router.default_log_id());
+        let signed_root = self.log.latest_root(log_id).await.map_err(log_error)?;
+        let proof = self.log.inclusion_proof(log_id, &record.leaf_hash, signed_root.tree_size).await
+            .map_err(log_error)?
+            .ok_or_else(|| Status::internal("Integrated leaf missing from the latest tree"))?;
+        self.signer.sign_cose_receipt(proof.leaf_index, signed_root.tree_size, &signed_root.root_hash, &proof.hashes).await
+            .map_err(|e| Status::internal(format!("Signing error: {}", e)))
+    }
+
+    /// Submits a single hash outside a `SubmitHash` stream, as the REST
+    /// gateway does, and waits for its response. `identity` comes from the
+    /// caller's client certificate and binds `proxy_id` as on the stream.
+    pub async fn submit_one(
+        &self,
+        tenant_id: String,
+        identity: Option<PeerIdentity>,
+        submission: HashSubmission,
+    ) -> Result<ReceiptResponse, Status> {
+        if self.require_client_identity && identity.is_none() {
+            return Err(Status::unauthenticated("Client certificate required"));
+        }
+        if let Some(identity) = &identity {
+            if let Err(status) = identity.check(&submission.proxy_id) {
+                metrics::record_submission("rejected");
+                return Err(status);
+            }
+        }
+        if let Err(status) = self.rate_limits.check(&tenant_id, &submission.proxy_id) {
+            metrics::record_submission("rejected");
+            return Err(status);
+        }
+        self.enqueue(tenant_id, submission).await
+    }
+
+    /// Queues a submission for the batch worker, bypassing identity checks
+    /// and rate limits, and waits for its response.
+    pub async fn enqueue(&self, tenant_id: String, submission: HashSubmission) -> Result<ReceiptResponse, Status> {
+        let (respond_to, mut results) = mpsc::channel(1);
+        let pending = PendingSubmission {
+            tenant_id,
+            span: submission_span(&submission),
+            submission,
+            respond_to,
+        };
+        self.batch_tx.send(pending).await
+            .map_err(|_| shutting_down())?;
+        results.recv().await
+            .ok_or_else(|| Status::internal("Submission was dropped"))
+    }
+
+    /// The tree holding the calling tenant's leaves.
+    async fn tenant_log<T>(&self, request: &Request<T>) -> Result<i64, Status> {
+        let tenant_id = auth::tenant_of(request)?;
+        self.router.log_id(&tenant_id).await
+            .map_err(|e| Status::internal(format!("Log routing error: {}", e)))
+    }
+
+    /// Signs and stores the promise for a submission whose leaf has been
+    /// queued; the integrator replaces it with the full receipt later. If
+    /// another submission stored a receipt for the leaf first, that receipt
+    /// is returned instead.
+    async fn issue_promise(
+        tenant_id: &str,
+        log_id: i64,
         sub: HashSubmission,
-        trillian: &TrillianClient,
+        queued: QueuedLeaf,
         signer: &Signer,
-        storage: &Storage,
-        kafka: &KafkaProducer,
+        storage: &dyn Storage,
+        max_merge_delay_secs: u64,
     ) -> anyhow::Result<ReceiptResponse> {
-        let leaf_index = trillian.queue_leaf(&sub.hash).await?;
-        let signed_root = trillian.get_current_root().await?;
-        let inclusion_proof = trillian.get_inclusion_proof(leaf_index, signed_root.tree_size).await?;
-        let receipt_jwt = signer.sign_receipt(
+        let promise_jwt = signer.sign_promise(
+            log_id,
             &sub.hash,
-            leaf_index,
-            &signed_root.root_hash,
-            &inclusion_proof,
+            max_merge_delay_secs,
             &sub.metadata,
         ).await?;
-        storage.store_receipt(
+        let stored = storage.store_pending_receipt(
+            tenant_id,
+            log_id,
+            &sub.proxy_id,
             &sub.hash,
-            leaf_index,
-            &signed_root.root_hash,
             &sub.metadata,
-            &receipt_jwt,
+            &promise_jwt,
         ).await?;
-        kafka.publish(&sub.hash, &receipt_jwt).await?;
+        if !stored {
+            let existing = storage.get_receipt(tenant_id, &sub.hash).await?
+                .ok_or_else(|| anyhow::anyhow!("Receipt conflict but no existing receipt"))?;
+            return Ok(ReceiptResponse {
+                duplicate: true,
+                ..receipt_response(&existing)
+            });
+        }
         Ok(ReceiptResponse {
-            receipt: receipt_jwt.into_bytes(),
-            leaf_index: leaf_index as u64,
+            receipt: promise_jwt.into_bytes(),
+            leaf_index: 0,
+            leaf_hash: sub.hash,
+            status: None,
+            state: ReceiptState::Pending as i32,
+            duplicate: queued.duplicate,
+            log_id,
+            revocation: None,
         })
     }
 }
@@ -108,77 +511,682 @@ impl Clone for AuditorService {
     fn clone(&self) -> Self {
         Self {
             storage: self.storage.clone(),
-            trillian: self.trillian.clone(),
+            log: self.log.clone(),
+            router: self.router.clone(),
             signer: self.signer.clone(),
-            kafka: self.kafka.clone(),
             batch_tx: self.batch_tx.clone(),
+            updates: self.updates.clone(),
+            rate_limits: self.rate_limits.clone(),
+            require_client_identity: self.require_client_identity,
+        }
+    }
+}
+
+/// Span for one submission, continuing the trace of the interaction that
+/// produced it when the submission carries one.
+fn submission_span(submission: &HashSubmission) -> Span {
+    let span = info_span!(
+        "submission",
+        leaf_hash = %hex::encode(&submission.hash),
+        leaf_index = tracing::field::Empty,
+        proxy_id = %submission.proxy_id,
+    );
+    if let Some(context) = telemetry::submission_context(&submission.trace_context) {
+        span.set_parent(context);
+    }
+    span
+}
+
+fn shutting_down() -> Status {
+    Status::unavailable("Auditor is shutting down")
+}
+
+fn log_error(e: anyhow::Error) -> Status {
+    Status::unavailable(format!("Log error: {}", e))
+}
+
+fn tree_size_arg(value: u64) -> Result<i64, Status> {
+    i64::try_from(value).map_err(|_| Status::invalid_argument("Tree position out of range"))
+}
+
+fn timestamp_arg(nanos: u64) -> Result<Option<chrono::DateTime<chrono::Utc>>, Status> {
+    if nanos == 0 {
+        return Ok(None);
+    }
+    let nanos = i64::try_from(nanos)
+        .map_err(|_| Status::invalid_argument("Timestamp out of range"))?;
+    Ok(Some(chrono::DateTime::from_timestamp(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+        .ok_or_else(|| Status::invalid_argument("Timestamp out of range"))?))
+}
+
+/// Turns dotted-path matches into the nested JSON object the context must
+/// contain, e.g. `a.b = c` becomes `{"a": {"b": "c"}}`.
+fn context_filter(fields: &HashMap<String, String>) -> Option<serde_json::Value> {
+    if fields.is_empty() {
+        return None;
+    }
+    let mut root = serde_json::Map::new();
+    for (path, value) in fields {
+        let mut keys: Vec<&str> = path.split('.').collect();
+        let last = keys.pop().unwrap_or_default();
+        let mut node = &mut root;
+        for key in keys {
+            let child = node.entry(key.to_string())
+                .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+            if !child.is_object() {
+                *child = serde_json::Value::Object(serde_json::Map::new());
+            }
+            node = child.as_object_mut().expect("object just ensured");
         }
+        node.insert(last.to_string(), serde_json::Value::String(value.clone()));
     }
+    Some(serde_json::Value::Object(root))
 }
 
+/// Page tokens are the opaque encoding of the last receipt's cursor.
+fn encode_page_token(record: &ReceiptRecord) -> String {
+    let raw = format!("{}:{}", record.created_at.timestamp_micros(), hex::encode(&record.leaf_hash));
+    BASE64_URL.encode(raw)
+}
+
+fn decode_page_token(token: &str) -> Result<ReceiptCursor, Status> {
+    let invalid = || Status::invalid_argument("Invalid page token");
+    let raw = BASE64_URL.decode(token).map_err(|_| invalid())?;
+    let raw = String::from_utf8(raw).map_err(|_| invalid())?;
+    let (micros, leaf_hash) = raw.split_once(':').ok_or_else(invalid)?;
+    let micros: i64 = micros.parse().map_err(|_| invalid())?;
+    Ok(ReceiptCursor {
+        created_at: chrono::DateTime::from_timestamp_micros(micros).ok_or_else(invalid)?,
+        leaf_hash: hex::decode(leaf_hash).map_err(|_| invalid())?,
+    })
+}
+
+/// Builds the response for a stored receipt.
+fn receipt_response(record: &ReceiptRecord) -> ReceiptResponse {
+    let state = if record.status == STATUS_PENDING {
+        ReceiptState::Pending
+    } else {
+        ReceiptState::Integrated
+    };
+    ReceiptResponse {
+        receipt: record.receipt_jwt.clone().into_bytes(),
+        leaf_index: record.leaf_index.unwrap_or_default() as u64,
+        leaf_hash: record.leaf_hash.clone(),
+        status: None,
+        state: state as i32,
+        duplicate: false,
+        log_id: record.log_id.unwrap_or_default(),
+        revocation: None,
+    }
+}
+
+fn revocation_message(record: &RevocationRecord) -> Revocation {
+    Revocation {
+        reason: record.reason.clone(),
+        actor: record.actor.clone(),
+        revoked_at_ns: record.revoked_at.timestamp_nanos_opt().unwrap_or(0) as u64,
+        statement: record.statement.clone().into_bytes(),
+        revocation_leaf_hash: record.revocation_leaf_hash.clone(),
+        log_id: record.log_id,
+    }
+}
+
+/// Builds the response sent back for a submission that was rejected or
+/// failed processing. The error is reported per item so one bad submission
+/// does not end the caller's stream.
+fn error_response(leaf_hash: Vec<u8>, status: Status) -> ReceiptResponse {
+    ReceiptResponse {
+        receipt: Vec::new(),
+        leaf_index: 0,
+        leaf_hash,
+        status: Some(RpcStatus {
+            code: status.code() as i32,
+            message: status.message().to_string(),
+            details: Vec::new(),
+        }),
+        state: ReceiptState::Unspecified as i32,
+        duplicate: false,
+        log_id: 0,
+        revocation: None,
+    }
+}
+
+fn failed_response(leaf_hash: Vec<u8>, err: &anyhow::Error) -> ReceiptResponse {
+    error_response(leaf_hash, Status::internal(err.to_string()))
+}
+
+/// Keeps a submission that failed at `stage` as a dead letter and builds its
+/// error response. Failing to store the dead letter is only logged; the
+/// caller still gets the original error.
+async fn give_up(
+    storage: &dyn Storage,
+    tenant_id: &str,
+    sub: &HashSubmission,
+    stage: &str,
+    err: &anyhow::Error,
+    attempts: u32,
+) -> ReceiptResponse {
+    let letter = DeadLetter {
+        tenant_id: tenant_id.to_string(),
+        proxy_id: sub.proxy_id.clone(),
+        leaf_hash: sub.hash.clone(),
+        metadata: sub.metadata.clone(),
+        timestamp_ns: sub.timestamp_ns as i64,
+        stage: stage.to_string(),
+        error: err.to_string(),
+        retryable: retry::is_retryable(err),
+        attempts: attempts as i32,
+    };
+    match storage.store_dead_letter(&letter).await {
+        Ok(id) => info!("Submission {} failed at {} and was kept as dead letter {}", hex::encode(&sub.hash), stage, id),
+        Err(e) => error!("Failed to store dead letter for {}: {}", hex::encode(&sub.hash), e),
+    }
+    metrics::record_dead_letter(stage);
+    failed_response(sub.hash.clone(), err)
+}
+
+/// Processes a batch with a single write to the log. Leaf indices are not
+/// final until the log sequences the batch, so each submission is answered
+/// with a signed promise and the integrator issues the receipt proper.
+///
+/// Submissions are idempotent: a hash that already has a receipt is answered
+/// with that receipt and never sent to the log again, and repeats within the
+/// batch share the first submission's result. Either way the response is
+/// marked as a duplicate.
+///
+/// Each stage retries transient failures on its own. A submission that still
+/// fails is answered with an error and kept as a dead letter for replay.
+#[tracing::instrument(skip_all, fields(batch_size = batch.len()))]
 async fn process_batch(
-    batch: Vec<HashSubmission>,
-    trillian: Arc<TrillianClient>,
+    batch: Vec<PendingSubmission>,
+    log: Arc<dyn TransparencyLog>,
+    router: Arc<LogRouter>,
     signer: Arc<Signer>,
-    storage: Arc<Storage>,
-    kafka: Arc<KafkaProducer>,
+    storage: Arc<dyn Storage>,
+    max_merge_delay_secs: u64,
+    retry_cfg: RetryConfig,
 ) {
-    for sub in batch {
-        if let Err(e) = AuditorService::process_single_submission(sub, &trillian, &signer, &storage, &kafka).await {
-            error!("Failed to process submission in batch: {}", e);
+    let retry_cfg = &retry_cfg;
+    metrics::BATCH_SIZE.observe(batch.len() as f64);
+    // Observed when dropped, on every return path
+    let _flush_timer = metrics::BATCH_FLUSH_SECONDS.start_timer();
+    // One batch serves many traces; link back to each submission
+    for pending in &batch {
+        Span::current().add_link(pending.span.context().span().span_context().clone());
+    }
+
+    // Receipts are per tenant, so everything is keyed by (tenant, hash)
+    let mut hashes_by_tenant: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
+    for pending in &batch {
+        hashes_by_tenant.entry(pending.tenant_id.clone())
+            .or_default()
+            .push(pending.submission.hash.clone());
+    }
+
+    let mut existing: HashMap<(String, Vec<u8>), ReceiptRecord> = HashMap::new();
+    for (tenant_id, hashes) in &hashes_by_tenant {
+        match retry(retry_cfg, "lookup", || storage.get_receipts(tenant_id, hashes)).await {
+            Ok(records) => existing.extend(records.into_iter()
+                .map(|record| ((record.tenant_id.clone(), record.leaf_hash.clone()), record))),
+            Err((e, attempts)) => {
+                error!("Failed to look up existing receipts for batch: {}", e);
+                let mut dead_lettered = HashSet::new();
+                for pending in batch {
+                    metrics::record_submission("failed");
+                    let key = (pending.tenant_id.clone(), pending.submission.hash.clone());
+                    let response = if dead_lettered.insert(key) {
+                        give_up(storage.as_ref(), &pending.tenant_id, &pending.submission, "lookup", &e, attempts).await
+                    } else {
+                        failed_response(pending.submission.hash.clone(), &e)
+                    };
+                    respond(pending.respond_to, response).await;
+                }
+                return;
+            }
+        }
+    }
+
+    // First submission of each (tenant, hash) not yet holding a receipt
+    let mut seen = HashSet::new();
+    let new_submissions: Vec<(String, HashSubmission)> = batch.iter()
+        .filter(|pending| !existing.contains_key(&(pending.tenant_id.clone(), pending.submission.hash.clone())))
+        .filter(|pending| seen.insert((pending.tenant_id.clone(), pending.submission.hash.clone())))
+        .map(|pending| (pending.tenant_id.clone(), pending.submission.clone()))
+        .collect();
+
+    // Each tenant's leaves go to its own tree
+    let mut issued: HashMap<(String, Vec<u8>), ReceiptResponse> = HashMap::new();
+    let mut tenant_logs: HashMap<String, i64> = HashMap::new();
+    let mut by_log: HashMap<i64, Vec<(String, HashSubmission)>> = HashMap::new();
+    for (tenant_id, sub) in new_submissions {
+        let log_id = match tenant_logs.get(&tenant_id) {
+            Some(log_id) => Ok(*log_id),
+            None => retry(retry_cfg, "route", || router.log_id(&tenant_id)).await,
+        };
+        match log_id {
+            Ok(log_id) => {
+                tenant_logs.insert(tenant_id.clone(), log_id);
+                by_log.entry(log_id).or_default().push((tenant_id, sub));
+            }
+            Err((e, attempts)) => {
+                error!("Failed to resolve log for tenant {}: {}", tenant_id, e);
+                let response = give_up(storage.as_ref(), &tenant_id, &sub, "route", &e, attempts).await;
+                issued.insert((tenant_id, sub.hash), response);
+            }
+        }
+    }
+
+    let logged = by_log.into_iter().map(|(log_id, submissions)| {
+        log_submissions(log_id, submissions, log.as_ref(), &signer, storage.as_ref(), max_merge_delay_secs, retry_cfg)
+    });
+    for responses in futures::future::join_all(logged).await {
+        issued.extend(responses);
+    }
+
+    // Responses go out in submission order once the whole batch has settled.
+    let mut answered = HashSet::new();
+    for PendingSubmission { tenant_id, submission, respond_to, span } in batch {
+        let key = (tenant_id, submission.hash);
+        let response = match existing.get(&key) {
+            Some(record) => ReceiptResponse {
+                duplicate: true,
+                ..receipt_response(record)
+            },
+            None => {
+                let mut response = issued[&key].clone();
+                if !answered.insert(key) && response.status.is_none() {
+                    response.duplicate = true;
+                }
+                response
+            }
+        };
+        metrics::record_submission(if response.status.is_some() {
+            "failed"
+        } else if response.duplicate {
+            "duplicate"
+        } else {
+            "accepted"
+        });
+        if response.status.is_none() {
+            span.record("leaf_index", response.leaf_index);
         }
+        respond(respond_to, response).await;
+    }
+}
+
+/// Adds one tree's share of a batch to the log and issues the promises.
+#[tracing::instrument(skip_all, fields(log_id = log_id, leaves = submissions.len()))]
+async fn log_submissions(
+    log_id: i64,
+    submissions: Vec<(String, HashSubmission)>,
+    log: &dyn TransparencyLog,
+    signer: &Signer,
+    storage: &dyn Storage,
+    max_merge_delay_secs: u64,
+    retry_cfg: &RetryConfig,
+) -> Vec<((String, Vec<u8>), ReceiptResponse)> {
+    let hashes: Vec<Vec<u8>> = submissions.iter()
+        .map(|(_, sub)| sub.hash.clone())
+        .collect();
+    // Appending is idempotent: leaves the log already holds come back as
+    // duplicates, so the whole call can be retried.
+    let queued = match retry(retry_cfg, "append", || log.append(log_id, &hashes)).await {
+        Ok(queued) => queued,
+        Err((e, attempts)) => {
+            error!("Failed to add batch of {} leaves to log {}: {}", hashes.len(), log_id, e);
+            let e = &e;
+            let failed = submissions.into_iter().map(|(tenant_id, sub)| async move {
+                let response = give_up(storage, &tenant_id, &sub, "append", e, attempts).await;
+                ((tenant_id, sub.hash), response)
+            });
+            return futures::future::join_all(failed).await;
+        }
+    };
+
+    let promises = submissions.into_iter()
+        .zip(queued)
+        .map(|((tenant_id, sub), queued)| async move {
+            let leaf_hash = sub.hash.clone();
+            let result = match queued {
+                Ok(queued) => retry(retry_cfg, "promise", || {
+                    AuditorService::issue_promise(&tenant_id, log_id, sub.clone(), queued, signer, storage, max_merge_delay_secs)
+                })
+                .await
+                .map_err(|(e, attempts)| ("promise", e, attempts)),
+                Err(e) => Err(("append", e, 1)),
+            };
+            let response = match result {
+                Ok(response) => response,
+                Err((stage, e, attempts)) => {
+                    error!("Failed to process submission in batch: {}", e);
+                    give_up(storage, &tenant_id, &sub, stage, &e, attempts).await
+                }
+            };
+            ((tenant_id, leaf_hash), response)
+        });
+    futures::future::join_all(promises).await
+}
+
+/// Starts a batch on its own task, first waiting for a running one to
+/// finish if `MAX_BATCHES_IN_FLIGHT` already are.
+async fn dispatch(in_flight: &mut JoinSet<()>, batch: impl Future<Output = ()> + Send + 'static) {
+    while in_flight.len() >= MAX_BATCHES_IN_FLIGHT {
+        if let Some(result) = in_flight.join_next().await {
+            log_batch_result(result);
+        }
+    }
+    in_flight.spawn(batch);
+}
+
+fn log_batch_result(result: Result<(), tokio::task::JoinError>) {
+    if let Err(e) = result {
+        error!("Batch task failed: {}", e);
+    }
+}
+
+/// Hands a response back to the originating stream's forwarding task.
+async fn respond(respond_to: mpsc::Sender<ReceiptResponse>, response: ReceiptResponse) {
+    let leaf_hash = hex::encode(&response.leaf_hash);
+    if respond_to.send(response).await.is_err() {
+        error!("Response stream closed before receipt {} was delivered", leaf_hash);
     }
 }
 
 pub async fn run(cfg: Config) -> anyhow::Result<()> {
-    let storage = Arc::new(Storage::new(&cfg.database.url).await?);
-    let trillian = Arc::new(TrillianClient::new(&cfg.trillian).await?);
-    let signer = Arc::new(Signer::new(&cfg.sigstore).await?);
-    let kafka = Arc::new(KafkaProducer::new(&cfg.kafka).await?);
+    let storage = storage::connect(&cfg.database).await?;
+    let log: Arc<dyn TransparencyLog> = match cfg.log.backend {
+        LogBackend::Trillian => Arc::new(TrillianClient::new(&cfg.trillian).await?),
+        LogBackend::Embedded => {
+            let store: Box<dyn LeafStore> = match cfg.log.embedded.store {
+                LeafStoreKind::Disk => Box::new(DiskLeafStore::new(&cfg.log.embedded.path)?),
+                LeafStoreKind::Database => Box::new(DatabaseLeafStore::new(storage.clone())),
+            };
+            info!("Using the embedded Merkle log ({:?} store)", cfg.log.embedded.store);
+            Arc::new(MerkleLog::new(store))
+        }
+    };
+    let signer = Arc::new(Signer::new(&cfg.signing).await?);
+    let events = Arc::new(EventSinks::new(&cfg).await?);
 
     // Batching channel
-    let (batch_tx, mut batch_rx): (UnboundedSender<HashSubmission>, UnboundedReceiver<HashSubmission>) = mpsc::unbounded_channel();
+    let (batch_tx, mut batch_rx) = mpsc::channel::<PendingSubmission>(cfg.limits.queue_capacity);
+
+    let router = Arc::new(LogRouter::new(&cfg.trillian, storage.clone()));
 
-    let trillian_clone = trillian.clone();
+    let log_clone = log.clone();
+    let router_clone = router.clone();
     let signer_clone = signer.clone();
     let storage_clone = storage.clone();
-    let kafka_clone = kafka.clone();
+    let max_merge_delay_secs = cfg.integrator.max_merge_delay_secs;
+    let retry_cfg = cfg.retry.clone();
+    let process = move |batch| process_batch(
+        batch,
+        log_clone.clone(),
+        router_clone.clone(),
+        signer_clone.clone(),
+        storage_clone.clone(),
+        max_merge_delay_secs,
+        retry_cfg.clone(),
+    );
 
-    tokio::spawn(async move {
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+    let worker_shutdown = shutdown_rx.clone();
+    let batch_worker = tokio::spawn(async move {
         let mut batch = Vec::new();
+        let mut in_flight = JoinSet::new();
         let mut interval = time::interval(Duration::from_millis(100));
+        let mut closing = false;
         loop {
             tokio::select! {
-                Some(sub) = batch_rx.recv() => {
+                sub = batch_rx.recv() => {
+                    let Some(sub) = sub else {
+                        // Closed and empty: everything queued has been taken
+                        break;
+                    };
                     batch.push(sub);
+                    metrics::QUEUE_DEPTH.set(batch_rx.len() as i64);
                     if batch.len() >= 100 {
-                        process_batch(batch, trillian_clone.clone(), signer_clone.clone(), storage_clone.clone(), kafka_clone.clone()).await;
-                        batch = Vec::new();
+                        dispatch(&mut in_flight, process(std::mem::take(&mut batch))).await;
                     }
                 }
                 _ = interval.tick() => {
+                    metrics::QUEUE_DEPTH.set(batch_rx.len() as i64);
                     if !batch.is_empty() {
-                        process_batch(batch, trillian_clone.clone(), signer_clone.clone(), storage_clone.clone(), kafka_clone.clone()).await;
-                        batch = Vec::new();
+                        dispatch(&mut in_flight, process(std::mem::take(&mut batch))).await;
                     }
                 }
+                Some(result) = in_flight.join_next(), if !in_flight.is_empty() => log_batch_result(result),
+                _ = shutdown_requested(worker_shutdown.clone()), if !closing => {
+                    // Refuse new submissions but keep draining what is queued
+                    batch_rx.close();
+                    closing = true;
+                }
             }
         }
+        if !batch.is_empty() {
+            dispatch(&mut in_flight, process(batch)).await;
+        }
+        while let Some(result) = in_flight.join_next().await {
+            log_batch_result(result);
+        }
+        info!("Batch queue drained");
     });
 
+    // Upgrades pending receipts and fans them out to waiting streams
+    let (updates, _) = broadcast::channel(1024);
+    let integrator = Integrator::new(
+        log.clone(),
+        router.clone(),
+        signer.clone(),
+        storage.clone(),
+        updates.clone(),
+        cfg.integrator.clone(),
+    );
+    tokio::spawn(integrator.run());
+    tokio::spawn(OutboxRelay::new(storage.clone(), events.clone(), cfg.outbox.clone()).run());
+
     let service = AuditorService {
-        storage,
-        trillian,
-        signer,
-        kafka,
+        storage: storage.clone(),
+        log: log.clone(),
+        router: router.clone(),
+        signer: signer.clone(),
         batch_tx,
+        updates,
+        rate_limits: Arc::new(RateLimits::new(&cfg.limits)),
+        require_client_identity: cfg.server.tls.as_ref()
+            .map_or(false, |tls| tls.client_ca_path.is_some()),
     };
 
+    let auth = ApiKeyAuth::new(&cfg.auth)?;
+    if let Some(http_addr) = &cfg.server.http_addr {
+        let app = gateway::router(service.clone(), auth.clone(), signer);
+        let http_addr: SocketAddr = http_addr.parse()?;
+        // Receipts and API keys must not cross the gateway in the clear
+        // when gRPC is protected
+        let tls = match &cfg.server.tls {
+            Some(tls_cfg) => Some(tls::gateway_tls_config(tls_cfg).await?),
+            None => None,
+        };
+        let listener = std::net::TcpListener::bind(http_addr)?;
+        info!("Starting REST gateway on {}{}", http_addr, if tls.is_some() { " with TLS" } else { "" });
+        let gateway_shutdown = shutdown_requested(shutdown_rx.clone());
+        tokio::spawn(async move {
+            if let Err(e) = gateway::serve(app, listener, tls, gateway_shutdown).await {
+                error!("REST gateway failed: {}", e);
+            }
+        });
+    }
+
+    if let Some(metrics_addr) = &cfg.server.metrics_addr {
+        tokio::spawn(metrics::serve(metrics_addr.parse()?, shutdown_requested(shutdown_rx.clone())));
+    }
+
+    let (reporter, health_service) = tonic_health::server::health_reporter();
+    tokio::spawn(health::monitor(
+        reporter,
+        storage.clone(),
+        log.clone(),
+        router.default_log_id(),
+        events,
+        shutdown_rx.clone(),
+    ));
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(crate::AUDITOR_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build()?;
+
+    tokio::spawn(async move {
+        wait_for_signal().await;
+        info!("Shutdown requested");
+        let _ = shutdown_tx.send(true);
+    });
+
     let addr = cfg.server.addr.parse()?;
+    let mut server = tonic::transport::Server::builder().trace_fn(telemetry::grpc_span);
+    if let Some(tls_cfg) = &cfg.server.tls {
+        server = server.tls_config(tls::server_tls_config(tls_cfg)?)?;
+    }
     info!("Starting auditor server on {}", addr);
-    tonic::transport::Server::builder()
-        .add_service(AuditorServer::new(service))
-        .serve(addr)
-        .await?;
+    // Without an admin key nobody could call it
+    let admin_service = auth.has_admin()
+        .then(|| AuditorAdminServer::with_interceptor(AdminService::new(storage, service.clone()), auth.clone()));
+    if admin_service.is_none() {
+        info!("No API key has the admin grant; not serving the admin service");
+    }
+    let mut serve = tokio::spawn(
+        server
+            .add_service(health_service)
+            .add_service(reflection_service)
+            .add_optional_service(admin_service)
+            .add_service(AuditorServer::with_interceptor(service, auth))
+            .serve_with_shutdown(addr, shutdown_requested(shutdown_rx.clone())),
+    );
+
+    tokio::select! {
+        result = &mut serve => {
+            // Stopped without being asked to; don't wait on anything else
+            result??;
+            return Ok(());
+        }
+        _ = shutdown_requested(shutdown_rx.clone()) => {}
+    }
+
+    // Answer every submission that made it into the queue before exiting
+    batch_worker.await?;
+
+    // Open streams may still be waiting on integrations; their receipts
+    // stay retrievable with GetReceipt once the grace period runs out.
+    let grace = Duration::from_secs(cfg.server.shutdown_grace_secs);
+    match time::timeout(grace, serve).await {
+        Ok(result) => result??,
+        Err(_) => info!("Closing streams still open after {}s", grace.as_secs()),
+    }
+    info!("Auditor stopped");
     Ok(())
 }
+
+/// Resolves once shutdown has been requested.
+async fn shutdown_requested(mut shutdown: watch::Receiver<bool>) {
+    while !*shutdown.borrow_and_update() {
+        if shutdown.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = sigterm.recv() => {}
+                }
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use anyhow::bail;
+    use super::*;
+    use crate::config::{DatabaseConfig, SigningConfig, TrillianConfig};
+    use crate::storage::SqliteStorage;
+    use crate::translog::{InclusionProof, Leaf, SignedRoot};
+
+    /// A log that is down, numbering its failures.
+    #[derive(Default)]
+    struct FailingLog {
+        failures: AtomicU32,
+    }
+
+    #[async_trait::async_trait]
+    impl TransparencyLog for FailingLog {
+        async fn append(&self, _log_id: i64, _leaf_values: &[Vec<u8>]) -> anyhow::Result<Vec<anyhow::Result<QueuedLeaf>>> {
+            bail!("log unavailable ({})", self.failures.fetch_add(1, Ordering::SeqCst) + 1)
+        }
+        async fn latest_root(&self, _log_id: i64) -> anyhow::Result<SignedRoot> {
+            bail!("log unavailable")
+        }
+        async fn inclusion_proof(&self, _log_id: i64, _leaf_value: &[u8], _tree_size: i64) -> anyhow::Result<Option<InclusionProof>> {
+            bail!("log unavailable")
+        }
+        async fn consistency_proof(&self, _log_id: i64, _first: i64, _second: i64) -> anyhow::Result<Vec<Vec<u8>>> {
+            bail!("log unavailable")
+        }
+        async fn leaves_by_range(&self, _log_id: i64, _start_index: i64, _count: i64) -> anyhow::Result<Vec<Leaf>> {
+            bail!("log unavailable")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failing_replay_updates_its_dead_letter() {
+        let storage: Arc<dyn Storage> = Arc::new(
+            SqliteStorage::new(&DatabaseConfig { url: "sqlite::memory:".to_string(), password: None }).await.unwrap(),
+        );
+        let log: Arc<dyn TransparencyLog> = Arc::new(FailingLog::default());
+        let router = Arc::new(LogRouter::new(&TrillianConfig::default(), storage.clone()));
+        let signer = Arc::new(Signer::new(&SigningConfig::default()).await.unwrap());
+        let retry_cfg = RetryConfig { max_attempts: 1, ..Default::default() };
+        // Submits the hash as the replay of a dead letter does
+        let submit = || async {
+            let (respond_to, mut results) = mpsc::channel(1);
+            let submission = HashSubmission { hash: vec![7; 32], proxy_id: "proxy".to_string(), ..Default::default() };
+            let pending = PendingSubmission { tenant_id: "tenant".to_string(), submission, respond_to, span: Span::none() };
+            process_batch(vec![pending], log.clone(), router.clone(), signer.clone(), storage.clone(), 60, retry_cfg.clone()).await;
+            results.recv().await.unwrap()
+        };
+
+        assert!(submit().await.status.is_some());
+        let letters = storage.dead_letters(None, false, 0, 10).await.unwrap();
+        assert_eq!(letters.len(), 1);
+        let id = letters[0].id;
+
+        assert!(submit().await.status.is_some());
+        let letters = storage.dead_letters(None, true, 0, 10).await.unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].id, id);
+        assert_eq!(letters[0].letter.attempts, 2);
+        assert!(letters[0].letter.error.contains("(2)"), "{}", letters[0].letter.error);
+
+        // Once replayed, a later failure is a dead letter of its own
+        storage.mark_replayed(id).await.unwrap();
+        assert!(submit().await.status.is_some());
+        let letters = storage.dead_letters(None, true, 0, 10).await.unwrap();
+        assert_eq!(letters.len(), 2);
+        assert_eq!(letters[1].letter.attempts, 1);
+    }
+}
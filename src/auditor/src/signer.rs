@@ -1,70 +1,144 @@
 use anyhow::Result;
-use ed25519_dalek::{SigningKey, Signer as EdSigner};
-use rand::rngs::OsRng;
 use serde::{Serialize, Deserialize};
-use base64::engine::general_purpose::STANDARD as BASE64;
-use base64::Engine;
-use crate::config::SigstoreConfig;
+use verillm_shared_utils::cose;
+use verillm_shared_utils::statements::{
+    sign_jws, Revocation, PAYLOAD_VERSION, PROMISE_DOMAIN, PROMISE_TYP, RECEIPT_DOMAIN, RECEIPT_TYP,
+};
+use crate::config::SigningConfig;
+use crate::keys::{Jwks, KeyRing};
+use crate::translog::{InclusionProof, SignedRoot};
 
+/// Payload of an inclusion receipt, issued as a compact JWS over its
+/// canonical encoding, so every field is signed.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Receipt {
+    pub domain: String,
+    pub version: u32,
+    pub log_id: i64,
     pub leaf_hash: String,
     pub leaf_index: i64,
+    /// Size of the tree `root_hash` is the root of, which the inclusion
+    /// proof is checked against.
+    pub tree_size: i64,
     pub root_hash: String,
     pub inclusion_proof: Vec<String>,
     pub timestamp: String,
     pub metadata: serde_json::Value,
-    pub signature: String,
-    pub public_key: String,
+}
+
+/// A signed promise to include a leaf in the log within
+/// `max_merge_delay_secs`, issued before the leaf has been sequenced (the
+/// equivalent of a Certificate Transparency SCT). Issued as a compact JWS.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReceiptPromise {
+    pub domain: String,
+    pub version: u32,
+    pub log_id: i64,
+    pub leaf_hash: String,
+    pub timestamp: String,
+    pub max_merge_delay_secs: u64,
+    pub metadata: serde_json::Value,
 }
 
 pub struct Signer {
-    signing_key: SigningKey,
-    public_key: Vec<u8>,
+    keys: KeyRing,
 }
 
 impl Signer {
-    pub async fn new(_cfg: &SigstoreConfig) -> Result<Self> {
-        let signing_key = SigningKey::generate(&mut OsRng);
-        let public_key = signing_key.verifying_key().to_bytes().to_vec();
-        Ok(Self { signing_key, public_key })
+    pub async fn new(cfg: &SigningConfig) -> Result<Self> {
+        Ok(Self { keys: KeyRing::load(cfg)? })
+    }
+
+    pub fn jwks(&self) -> Jwks {
+        self.keys.jwks()
     }
 
     pub async fn sign_receipt(
         &self,
+        log_id: i64,
         leaf_hash: &[u8],
-        leaf_index: i64,
-        root_hash: &[u8],
-        inclusion_proof: &[Vec<u8>],
+        proof: &InclusionProof,
+        signed_root: &SignedRoot,
         metadata: &[u8],
     ) -> Result<String> {
         let metadata: serde_json::Value = serde_json::from_slice(metadata)?;
-        let proof_hex: Vec<String> = inclusion_proof.iter()
+        let proof_hex: Vec<String> = proof.hashes.iter()
             .map(|h| hex::encode(h))
             .collect();
-        let timestamp = chrono::Utc::now().to_rfc3339();
-
-        let canonical_string = format!(
-            "{}:{}:{}:{}",
-            hex::encode(leaf_hash),
-            leaf_index,
-            hex::encode(root_hash),
-            timestamp
-        );
-        let signature = self.signing_key.sign(canonical_string.as_bytes());
+        let now = chrono::Utc::now();
+        let key = self.keys.active_at(now)?;
 
         let receipt = Receipt {
+            domain: RECEIPT_DOMAIN.to_string(),
+            version: PAYLOAD_VERSION,
+            log_id,
             leaf_hash: hex::encode(leaf_hash),
-            leaf_index,
-            root_hash: hex::encode(root_hash),
+            leaf_index: proof.leaf_index,
+            tree_size: signed_root.tree_size,
+            root_hash: hex::encode(&signed_root.root_hash),
             inclusion_proof: proof_hex,
-            timestamp: timestamp.clone(),
+            timestamp: now.to_rfc3339(),
             metadata,
-            signature: BASE64.encode(signature.to_bytes()),
-            public_key: BASE64.encode(&self.public_key),
         };
 
-        let receipt_json = serde_json::to_string(&receipt)?;
-        Ok(receipt_json)
+        sign_jws(&key.signing_key, &key.kid, RECEIPT_TYP, &receipt)
+    }
+
+    pub async fn sign_promise(
+        &self,
+        log_id: i64,
+        leaf_hash: &[u8],
+        max_merge_delay_secs: u64,
+        metadata: &[u8],
+    ) -> Result<String> {
+        let metadata: serde_json::Value = serde_json::from_slice(metadata)?;
+        let now = chrono::Utc::now();
+        let key = self.keys.active_at(now)?;
+
+        let promise = ReceiptPromise {
+            domain: PROMISE_DOMAIN.to_string(),
+            version: PAYLOAD_VERSION,
+            log_id,
+            leaf_hash: hex::encode(leaf_hash),
+            timestamp: now.to_rfc3339(),
+            max_merge_delay_secs,
+            metadata,
+        };
+
+        sign_jws(&key.signing_key, &key.kid, PROMISE_TYP, &promise)
+    }
+
+    /// COSE_Sign1 inclusion receipt in the form IETF SCITT uses, signed
+    /// over the tree root.
+    pub async fn sign_cose_receipt(
+        &self,
+        leaf_index: i64,
+        tree_size: i64,
+        root_hash: &[u8],
+        inclusion_proof: &[Vec<u8>],
+    ) -> Result<Vec<u8>> {
+        let key = self.keys.active_at(chrono::Utc::now())?;
+        cose::sign_receipt(&key.signing_key, &key.kid, leaf_index, tree_size, root_hash, inclusion_proof)
+    }
+
+    pub async fn sign_revocation(
+        &self,
+        log_id: i64,
+        leaf_hash: &[u8],
+        reason: &str,
+        actor: &str,
+        revoked_at: chrono::DateTime<chrono::Utc>,
+    ) -> Result<String> {
+        let key = self.keys.active_at(revoked_at)?;
+        let revocation = Revocation::sign(
+            &key.signing_key,
+            &key.kid,
+            log_id,
+            &hex::encode(leaf_hash),
+            reason,
+            actor,
+            revoked_at.to_rfc3339(),
+        )?;
+        Ok(serde_json::to_string(&revocation)?)
     }
 }
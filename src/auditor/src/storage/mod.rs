@@ -0,0 +1,218 @@
+mod postgres;
+mod sqlite;
+
+use std::sync::Arc;
+use anyhow::Result;
+use async_trait::async_trait;
+use crate::config::DatabaseConfig;
+
+pub use postgres::PgStorage;
+pub use sqlite::SqliteStorage;
+
+pub const STATUS_PENDING: &str = "pending";
+pub const STATUS_INTEGRATED: &str = "integrated";
+
+pub struct ReceiptRecord {
+    pub tenant_id: String,
+    pub log_id: Option<i64>,
+    pub proxy_id: Option<String>,
+    pub leaf_hash: Vec<u8>,
+    pub leaf_index: Option<i64>,
+    pub root_hash: Option<Vec<u8>>,
+    pub context: serde_json::Value,
+    pub receipt_jwt: String,
+    pub status: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Filters for [`Storage::list_receipts`]; `None` fields match everything.
+#[derive(Debug, Default)]
+pub struct ReceiptFilter {
+    pub created_after: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_before: Option<chrono::DateTime<chrono::Utc>>,
+    pub model_id: Option<String>,
+    pub proxy_id: Option<String>,
+    /// JSON object the receipt context must contain.
+    pub context: Option<serde_json::Value>,
+}
+
+/// Position after the last receipt of a page. Receipts are ordered by
+/// creation time, ties broken by leaf hash, which is unique per tenant.
+#[derive(Debug, Clone)]
+pub struct ReceiptCursor {
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub leaf_hash: Vec<u8>,
+}
+
+/// A receipt still waiting for its leaf to be integrated into the log.
+pub struct PendingRecord {
+    pub tenant_id: String,
+    pub log_id: Option<i64>,
+    pub leaf_hash: Vec<u8>,
+    pub context: serde_json::Value,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// How many checks have found the leaf missing from the log.
+    pub attempts: i32,
+}
+
+/// An integrated receipt waiting in the outbox to be announced to the
+/// event sinks.
+pub struct OutboxEvent {
+    pub id: i64,
+    pub tenant_id: String,
+    pub log_id: i64,
+    pub leaf_hash: Vec<u8>,
+    pub leaf_index: i64,
+    pub receipt_jwt: String,
+}
+
+/// A submission given up on, kept so operators can inspect and replay it.
+pub struct DeadLetter {
+    pub tenant_id: String,
+    pub proxy_id: String,
+    pub leaf_hash: Vec<u8>,
+    pub metadata: Vec<u8>,
+    pub timestamp_ns: i64,
+    /// Processing stage that failed.
+    pub stage: String,
+    pub error: String,
+    /// Whether the last error was transient, i.e. retries ran out.
+    pub retryable: bool,
+    pub attempts: i32,
+}
+
+pub struct DeadLetterRecord {
+    pub id: i64,
+    pub letter: DeadLetter,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub replayed_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// The withdrawal of a receipt, with the signed statement that was logged.
+pub struct RevocationRecord {
+    pub tenant_id: String,
+    pub leaf_hash: Vec<u8>,
+    pub log_id: i64,
+    pub reason: String,
+    pub actor: String,
+    pub statement: String,
+    /// Leaf appended to the log for the statement.
+    pub revocation_leaf_hash: Vec<u8>,
+    pub revoked_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Receipt persistence. Receipts are scoped by tenant: a leaf hash
+/// identifies a receipt only together with the tenant that owns it.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Round-trips a trivial query, for health checks.
+    async fn ping(&self) -> Result<()>;
+
+    /// Stores the signed promise issued when a leaf is queued. Returns
+    /// `false` if a receipt for the leaf already exists, leaving it intact.
+    async fn store_pending_receipt(
+        &self,
+        tenant_id: &str,
+        log_id: i64,
+        proxy_id: &str,
+        leaf_hash: &[u8],
+        metadata: &[u8],
+        promise_jwt: &str,
+    ) -> Result<bool>;
+
+    /// Receipts due a check against the log, longest due first. A receipt is
+    /// due once created and again once its `defer_pending` delay is over, so
+    /// leaves that never integrate cannot starve the ones behind them.
+    async fn pending_receipts(&self, limit: i64) -> Result<Vec<PendingRecord>>;
+
+    /// Counts a check that found the leaf missing from the log, and leaves
+    /// the receipt out of `pending_receipts` until `retry_at`.
+    async fn defer_pending(&self, tenant_id: &str, leaf_hash: &[u8], retry_at: chrono::DateTime<chrono::Utc>) -> Result<()>;
+
+    /// Replaces a pending promise with the full inclusion receipt and, in
+    /// the same transaction, queues its event in the outbox.
+    async fn mark_integrated(
+        &self,
+        tenant_id: &str,
+        log_id: i64,
+        leaf_hash: &[u8],
+        leaf_index: i64,
+        root_hash: &[u8],
+        receipt_jwt: &str,
+    ) -> Result<()>;
+
+    async fn get_receipt(&self, tenant_id: &str, leaf_hash: &[u8]) -> Result<Option<ReceiptRecord>>;
+
+    /// Fetches whichever of the given leaves already have a receipt for the tenant.
+    async fn get_receipts(&self, tenant_id: &str, leaf_hashes: &[Vec<u8>]) -> Result<Vec<ReceiptRecord>>;
+
+    /// Returns up to `limit` of the tenant's receipts matching `filter`,
+    /// starting after `after`.
+    async fn list_receipts(
+        &self,
+        tenant_id: &str,
+        filter: &ReceiptFilter,
+        after: Option<&ReceiptCursor>,
+        limit: i64,
+    ) -> Result<Vec<ReceiptRecord>>;
+
+    /// Leaf values of an embedded Merkle log, in index order.
+    async fn merkle_leaves(&self, log_id: i64) -> Result<Vec<Vec<u8>>>;
+
+    /// Appends leaves at `start_index..` atomically; fails if another writer
+    /// took any of those indices first.
+    async fn append_merkle_leaves(&self, log_id: i64, start_index: i64, leaf_values: &[Vec<u8>]) -> Result<()>;
+
+    /// Outbox events `sink` has not published yet: the first `limit` of
+    /// each log, in leaf order, so a backlog in one log can't hold up the
+    /// others.
+    async fn undelivered_events(&self, sink: &str, limit: i64) -> Result<Vec<OutboxEvent>>;
+
+    /// Records that `sink` published the events.
+    async fn mark_delivered(&self, sink: &str, ids: &[i64]) -> Result<()>;
+
+    /// Marks events every one of `sinks` has published as delivered, which
+    /// takes them out of the outbox scans.
+    async fn complete_deliveries(&self, sinks: &[&str]) -> Result<()>;
+
+    /// Deletes events marked delivered before `before`; returns how many.
+    async fn prune_delivered_events(&self, before: chrono::DateTime<chrono::Utc>) -> Result<u64>;
+
+    /// Records a revocation. Returns `false` if the receipt was already
+    /// revoked, leaving the earlier revocation intact.
+    async fn store_revocation(&self, revocation: &RevocationRecord) -> Result<bool>;
+
+    async fn get_revocation(&self, tenant_id: &str, leaf_hash: &[u8]) -> Result<Option<RevocationRecord>>;
+
+    /// Keeps a dead letter and returns its ID. When the tenant's submission
+    /// of the same hash is already a dead letter awaiting replay, as it is
+    /// when a replay fails, that one takes the new attempts and error
+    /// instead, so failures do not pile up copies.
+    async fn store_dead_letter(&self, letter: &DeadLetter) -> Result<i64>;
+
+    /// Dead letters with IDs above `after_id`, in ID order, optionally for
+    /// one tenant only.
+    async fn dead_letters(
+        &self,
+        tenant_id: Option<&str>,
+        include_replayed: bool,
+        after_id: i64,
+        limit: i64,
+    ) -> Result<Vec<DeadLetterRecord>>;
+
+    async fn get_dead_letter(&self, id: i64) -> Result<Option<DeadLetterRecord>>;
+
+    async fn mark_replayed(&self, id: i64) -> Result<()>;
+
+    async fn tenant_log_id(&self, tenant_id: &str) -> Result<Option<i64>>;
+}
+
+/// Opens the database named by `database.url`, running its migrations:
+/// `sqlite:` URLs get an embedded SQLite database, anything else Postgres.
+pub async fn connect(cfg: &DatabaseConfig) -> Result<Arc<dyn Storage>> {
+    if cfg.is_sqlite() {
+        Ok(Arc::new(SqliteStorage::new(cfg).await?))
+    } else {
+        Ok(Arc::new(PgStorage::new(cfg).await?))
+    }
+}
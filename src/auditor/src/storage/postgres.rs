@@ -0,0 +1,529 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Row};
+use sqlx::postgres::{PgPoolOptions, PgRow};
+use anyhow::Result;
+use crate::config::DatabaseConfig;
+use crate::metrics;
+use super::{
+    DeadLetter, DeadLetterRecord, OutboxEvent, PendingRecord, ReceiptCursor, ReceiptFilter, ReceiptRecord,
+    RevocationRecord, Storage, STATUS_INTEGRATED, STATUS_PENDING,
+};
+
+pub struct PgStorage {
+    pool: PgPool,
+}
+
+impl PgStorage {
+    pub async fn new(cfg: &DatabaseConfig) -> Result<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(10)
+            .connect_with(cfg.connect_options()?)
+            .await?;
+        sqlx::migrate!("./migrations")
+            .run(&pool)
+            .await?;
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl Storage for PgStorage {
+    async fn ping(&self) -> Result<()> {
+        metrics::timed("postgres", "ping", sqlx::query("SELECT 1").execute(&self.pool)).await?;
+        Ok(())
+    }
+
+    async fn store_pending_receipt(
+        &self,
+        tenant_id: &str,
+        log_id: i64,
+        proxy_id: &str,
+        leaf_hash: &[u8],
+        metadata: &[u8],
+        promise_jwt: &str,
+    ) -> Result<bool> {
+        let context: serde_json::Value = serde_json::from_slice(metadata)?;
+        let query = sqlx::query(
+            r#"
+            INSERT INTO receipts (tenant_id, log_id, proxy_id, leaf_hash, context, receipt_jwt, status, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
+            ON CONFLICT (tenant_id, leaf_hash) DO NOTHING
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(log_id)
+        .bind(proxy_id)
+        .bind(leaf_hash)
+        .bind(context)
+        .bind(promise_jwt)
+        .bind(STATUS_PENDING)
+        .execute(&self.pool);
+        let result = metrics::timed("postgres", "store_pending_receipt", query).await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn pending_receipts(&self, limit: i64) -> Result<Vec<PendingRecord>> {
+        let query = sqlx::query(
+            r#"
+            SELECT tenant_id, log_id, leaf_hash, context, created_at, integrate_attempts
+            FROM receipts
+            WHERE status = $1 AND COALESCE(next_integrate_at, created_at) <= NOW()
+            ORDER BY COALESCE(next_integrate_at, created_at)
+            LIMIT $2
+            "#,
+        )
+        .bind(STATUS_PENDING)
+        .bind(limit)
+        .fetch_all(&self.pool);
+        let rows = metrics::timed("postgres", "pending_receipts", query).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(PendingRecord {
+                    tenant_id: row.try_get("tenant_id")?,
+                    log_id: row.try_get("log_id")?,
+                    leaf_hash: row.try_get("leaf_hash")?,
+                    context: row.try_get("context")?,
+                    created_at: row.try_get("created_at")?,
+                    attempts: row.try_get("integrate_attempts")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn defer_pending(&self, tenant_id: &str, leaf_hash: &[u8], retry_at: DateTime<Utc>) -> Result<()> {
+        let query = sqlx::query(
+            r#"
+            UPDATE receipts
+            SET integrate_attempts = integrate_attempts + 1, next_integrate_at = $3
+            WHERE tenant_id = $1 AND leaf_hash = $2 AND status = $4
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(leaf_hash)
+        .bind(retry_at)
+        .bind(STATUS_PENDING)
+        .execute(&self.pool);
+        metrics::timed("postgres", "defer_pending", query).await?;
+        Ok(())
+    }
+
+    async fn mark_integrated(
+        &self,
+        tenant_id: &str,
+        log_id: i64,
+        leaf_hash: &[u8],
+        leaf_index: i64,
+        root_hash: &[u8],
+        receipt_jwt: &str,
+    ) -> Result<()> {
+        let integrate = async {
+            let mut tx = self.pool.begin().await?;
+            let updated = sqlx::query(
+                r#"
+                UPDATE receipts
+                SET log_id = $3, leaf_index = $4, root_hash = $5, receipt_jwt = $6, status = $7, integrated_at = NOW()
+                WHERE tenant_id = $1 AND leaf_hash = $2 AND status = $8
+                "#,
+            )
+            .bind(tenant_id)
+            .bind(leaf_hash)
+            .bind(log_id)
+            .bind(leaf_index)
+            .bind(root_hash)
+            .bind(receipt_jwt)
+            .bind(STATUS_INTEGRATED)
+            .bind(STATUS_PENDING)
+            .execute(&mut *tx)
+            .await?;
+            // Only the upgrade that actually happened announces the receipt
+            if updated.rows_affected() == 1 {
+                sqlx::query(
+                    r#"
+                    INSERT INTO event_outbox (tenant_id, log_id, leaf_hash, leaf_index, receipt_jwt)
+                    VALUES ($1, $2, $3, $4, $5)
+                    "#,
+                )
+                .bind(tenant_id)
+                .bind(log_id)
+                .bind(leaf_hash)
+                .bind(leaf_index)
+                .bind(receipt_jwt)
+                .execute(&mut *tx)
+                .await?;
+            }
+            tx.commit().await
+        };
+        metrics::timed("postgres", "mark_integrated", integrate).await?;
+        Ok(())
+    }
+
+    async fn get_receipt(&self, tenant_id: &str, leaf_hash: &[u8]) -> Result<Option<ReceiptRecord>> {
+        let query = sqlx::query(
+            r#"
+            SELECT tenant_id, log_id, proxy_id, leaf_hash, leaf_index, root_hash, context, receipt_jwt, status, created_at
+            FROM receipts
+            WHERE tenant_id = $1 AND leaf_hash = $2
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(leaf_hash)
+        .fetch_optional(&self.pool);
+        let row = metrics::timed("postgres", "get_receipt", query).await?;
+        row.as_ref().map(receipt_record).transpose()
+    }
+
+    async fn get_receipts(&self, tenant_id: &str, leaf_hashes: &[Vec<u8>]) -> Result<Vec<ReceiptRecord>> {
+        let query = sqlx::query(
+            r#"
+            SELECT tenant_id, log_id, proxy_id, leaf_hash, leaf_index, root_hash, context, receipt_jwt, status, created_at
+            FROM receipts
+            WHERE tenant_id = $1 AND leaf_hash = ANY($2)
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(leaf_hashes)
+        .fetch_all(&self.pool);
+        let rows = metrics::timed("postgres", "get_receipts", query).await?;
+        rows.iter().map(receipt_record).collect()
+    }
+
+    async fn list_receipts(
+        &self,
+        tenant_id: &str,
+        filter: &ReceiptFilter,
+        after: Option<&ReceiptCursor>,
+        limit: i64,
+    ) -> Result<Vec<ReceiptRecord>> {
+        let query = sqlx::query(
+            r#"
+            SELECT tenant_id, log_id, proxy_id, leaf_hash, leaf_index, root_hash, context, receipt_jwt, status, created_at
+            FROM receipts
+            WHERE tenant_id = $1
+              AND ($2::timestamptz IS NULL OR created_at >= $2)
+              AND ($3::timestamptz IS NULL OR created_at < $3)
+              AND ($4::text IS NULL OR context->'request_headers'->>'x-model-id' = $4)
+              AND ($5::text IS NULL OR proxy_id = $5)
+              AND ($6::jsonb IS NULL OR context @> $6)
+              AND ($7::timestamptz IS NULL OR (created_at, leaf_hash) > ($7, $8::bytea))
+            ORDER BY created_at, leaf_hash
+            LIMIT $9
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(filter.created_after)
+        .bind(filter.created_before)
+        .bind(filter.model_id.as_deref())
+        .bind(filter.proxy_id.as_deref())
+        .bind(filter.context.as_ref())
+        .bind(after.map(|cursor| cursor.created_at))
+        .bind(after.map(|cursor| cursor.leaf_hash.clone()))
+        .bind(limit)
+        .fetch_all(&self.pool);
+        let rows = metrics::timed("postgres", "list_receipts", query).await?;
+        rows.iter().map(receipt_record).collect()
+    }
+
+    async fn merkle_leaves(&self, log_id: i64) -> Result<Vec<Vec<u8>>> {
+        let query = sqlx::query_scalar(
+            r#"
+            SELECT leaf_value
+            FROM merkle_leaves
+            WHERE log_id = $1
+            ORDER BY leaf_index
+            "#,
+        )
+        .bind(log_id)
+        .fetch_all(&self.pool);
+        Ok(metrics::timed("postgres", "merkle_leaves", query).await?)
+    }
+
+    async fn append_merkle_leaves(&self, log_id: i64, start_index: i64, leaf_values: &[Vec<u8>]) -> Result<()> {
+        let indices: Vec<i64> = (0..leaf_values.len() as i64).map(|i| start_index + i).collect();
+        let query = sqlx::query(
+            r#"
+            INSERT INTO merkle_leaves (log_id, leaf_index, leaf_value)
+            SELECT $1, leaf_index, leaf_value
+            FROM UNNEST($2::bigint[], $3::bytea[]) AS leaves(leaf_index, leaf_value)
+            "#,
+        )
+        .bind(log_id)
+        .bind(&indices)
+        .bind(leaf_values)
+        .execute(&self.pool);
+        metrics::timed("postgres", "append_merkle_leaves", query).await?;
+        Ok(())
+    }
+
+    async fn undelivered_events(&self, sink: &str, limit: i64) -> Result<Vec<OutboxEvent>> {
+        let query = sqlx::query(
+            r#"
+            SELECT e.id, e.tenant_id, e.log_id, e.leaf_hash, e.leaf_index, e.receipt_jwt
+            FROM (SELECT DISTINCT log_id FROM event_outbox WHERE delivered_at IS NULL) logs
+            CROSS JOIN LATERAL (
+                SELECT o.*
+                FROM event_outbox o
+                WHERE o.log_id = logs.log_id
+                  AND o.delivered_at IS NULL
+                  AND NOT EXISTS (SELECT 1 FROM event_deliveries d WHERE d.event_id = o.id AND d.sink = $1)
+                ORDER BY o.leaf_index
+                LIMIT $2
+            ) e
+            ORDER BY e.log_id, e.leaf_index
+            "#,
+        )
+        .bind(sink)
+        .bind(limit)
+        .fetch_all(&self.pool);
+        let rows = metrics::timed("postgres", "undelivered_events", query).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(OutboxEvent {
+                    id: row.try_get("id")?,
+                    tenant_id: row.try_get("tenant_id")?,
+                    log_id: row.try_get("log_id")?,
+                    leaf_hash: row.try_get("leaf_hash")?,
+                    leaf_index: row.try_get("leaf_index")?,
+                    receipt_jwt: row.try_get("receipt_jwt")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn mark_delivered(&self, sink: &str, ids: &[i64]) -> Result<()> {
+        let query = sqlx::query(
+            r#"
+            INSERT INTO event_deliveries (event_id, sink)
+            SELECT UNNEST($1::BIGINT[]), $2
+            ON CONFLICT (event_id, sink) DO NOTHING
+            "#,
+        )
+        .bind(ids)
+        .bind(sink)
+        .execute(&self.pool);
+        metrics::timed("postgres", "mark_delivered", query).await?;
+        Ok(())
+    }
+
+    async fn complete_deliveries(&self, sinks: &[&str]) -> Result<()> {
+        let query = sqlx::query(
+            r#"
+            UPDATE event_outbox o
+            SET delivered_at = NOW()
+            WHERE o.delivered_at IS NULL
+              AND NOT EXISTS (
+                  SELECT 1
+                  FROM UNNEST($1::TEXT[]) AS sinks(sink)
+                  WHERE NOT EXISTS (SELECT 1 FROM event_deliveries d WHERE d.event_id = o.id AND d.sink = sinks.sink)
+              )
+            "#,
+        )
+        .bind(sinks)
+        .execute(&self.pool);
+        metrics::timed("postgres", "complete_deliveries", query).await?;
+        Ok(())
+    }
+
+    async fn prune_delivered_events(&self, before: DateTime<Utc>) -> Result<u64> {
+        let query = sqlx::query("DELETE FROM event_outbox WHERE delivered_at < $1")
+            .bind(before)
+            .execute(&self.pool);
+        let result = metrics::timed("postgres", "prune_delivered_events", query).await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn store_revocation(&self, revocation: &RevocationRecord) -> Result<bool> {
+        let query = sqlx::query(
+            r#"
+            INSERT INTO revocations (tenant_id, leaf_hash, log_id, reason, actor, statement, revocation_leaf_hash, revoked_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (tenant_id, leaf_hash) DO NOTHING
+            "#,
+        )
+        .bind(&revocation.tenant_id)
+        .bind(&revocation.leaf_hash)
+        .bind(revocation.log_id)
+        .bind(&revocation.reason)
+        .bind(&revocation.actor)
+        .bind(&revocation.statement)
+        .bind(&revocation.revocation_leaf_hash)
+        .bind(revocation.revoked_at)
+        .execute(&self.pool);
+        let result = metrics::timed("postgres", "store_revocation", query).await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn get_revocation(&self, tenant_id: &str, leaf_hash: &[u8]) -> Result<Option<RevocationRecord>> {
+        let query = sqlx::query(
+            r#"
+            SELECT tenant_id, leaf_hash, log_id, reason, actor, statement, revocation_leaf_hash, revoked_at
+            FROM revocations
+            WHERE tenant_id = $1 AND leaf_hash = $2
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(leaf_hash)
+        .fetch_optional(&self.pool);
+        let row = metrics::timed("postgres", "get_revocation", query).await?;
+        row.map(|row| {
+            Ok(RevocationRecord {
+                tenant_id: row.try_get("tenant_id")?,
+                leaf_hash: row.try_get("leaf_hash")?,
+                log_id: row.try_get("log_id")?,
+                reason: row.try_get("reason")?,
+                actor: row.try_get("actor")?,
+                statement: row.try_get("statement")?,
+                revocation_leaf_hash: row.try_get("revocation_leaf_hash")?,
+                revoked_at: row.try_get("revoked_at")?,
+            })
+        })
+        .transpose()
+    }
+
+    async fn store_dead_letter(&self, letter: &DeadLetter) -> Result<i64> {
+        let query = sqlx::query_scalar(
+            r#"
+            UPDATE dead_letters
+            SET stage = $3, error = $4, retryable = $5, attempts = attempts + $6
+            WHERE id = (
+                SELECT id FROM dead_letters
+                WHERE tenant_id = $1 AND leaf_hash = $2 AND replayed_at IS NULL
+                ORDER BY id
+                LIMIT 1
+            )
+            RETURNING id
+            "#,
+        )
+        .bind(&letter.tenant_id)
+        .bind(&letter.leaf_hash)
+        .bind(&letter.stage)
+        .bind(&letter.error)
+        .bind(letter.retryable)
+        .bind(letter.attempts)
+        .fetch_optional(&self.pool);
+        if let Some(id) = metrics::timed("postgres", "update_dead_letter", query).await? {
+            return Ok(id);
+        }
+
+        let query = sqlx::query_scalar(
+            r#"
+            INSERT INTO dead_letters (tenant_id, proxy_id, leaf_hash, metadata, timestamp_ns, stage, error, retryable, attempts)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING id
+            "#,
+        )
+        .bind(&letter.tenant_id)
+        .bind(&letter.proxy_id)
+        .bind(&letter.leaf_hash)
+        .bind(&letter.metadata)
+        .bind(letter.timestamp_ns)
+        .bind(&letter.stage)
+        .bind(&letter.error)
+        .bind(letter.retryable)
+        .bind(letter.attempts)
+        .fetch_one(&self.pool);
+        Ok(metrics::timed("postgres", "store_dead_letter", query).await?)
+    }
+
+    async fn dead_letters(
+        &self,
+        tenant_id: Option<&str>,
+        include_replayed: bool,
+        after_id: i64,
+        limit: i64,
+    ) -> Result<Vec<DeadLetterRecord>> {
+        let query = sqlx::query(
+            r#"
+            SELECT id, tenant_id, proxy_id, leaf_hash, metadata, timestamp_ns, stage, error, retryable, attempts, created_at, replayed_at
+            FROM dead_letters
+            WHERE id > $1
+              AND ($2::text IS NULL OR tenant_id = $2)
+              AND ($3 OR replayed_at IS NULL)
+            ORDER BY id
+            LIMIT $4
+            "#,
+        )
+        .bind(after_id)
+        .bind(tenant_id)
+        .bind(include_replayed)
+        .bind(limit)
+        .fetch_all(&self.pool);
+        let rows = metrics::timed("postgres", "dead_letters", query).await?;
+        rows.iter().map(dead_letter_record).collect()
+    }
+
+    async fn get_dead_letter(&self, id: i64) -> Result<Option<DeadLetterRecord>> {
+        let query = sqlx::query(
+            r#"
+            SELECT id, tenant_id, proxy_id, leaf_hash, metadata, timestamp_ns, stage, error, retryable, attempts, created_at, replayed_at
+            FROM dead_letters
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool);
+        let row = metrics::timed("postgres", "get_dead_letter", query).await?;
+        row.as_ref().map(dead_letter_record).transpose()
+    }
+
+    async fn mark_replayed(&self, id: i64) -> Result<()> {
+        let query = sqlx::query(
+            r#"
+            UPDATE dead_letters
+            SET replayed_at = NOW()
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .execute(&self.pool);
+        metrics::timed("postgres", "mark_replayed", query).await?;
+        Ok(())
+    }
+
+    async fn tenant_log_id(&self, tenant_id: &str) -> Result<Option<i64>> {
+        let query = sqlx::query_scalar(
+            r#"
+            SELECT log_id
+            FROM tenant_logs
+            WHERE tenant_id = $1
+            "#,
+        )
+        .bind(tenant_id)
+        .fetch_optional(&self.pool);
+        Ok(metrics::timed("postgres", "tenant_log_id", query).await?)
+    }
+}
+
+fn receipt_record(row: &PgRow) -> Result<ReceiptRecord> {
+    Ok(ReceiptRecord {
+        tenant_id: row.try_get("tenant_id")?,
+        log_id: row.try_get("log_id")?,
+        proxy_id: row.try_get("proxy_id")?,
+        leaf_hash: row.try_get("leaf_hash")?,
+        leaf_index: row.try_get("leaf_index")?,
+        root_hash: row.try_get("root_hash")?,
+        context: row.try_get("context")?,
+        receipt_jwt: row.try_get("receipt_jwt")?,
+        status: row.try_get("status")?,
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+fn dead_letter_record(row: &PgRow) -> Result<DeadLetterRecord> {
+    Ok(DeadLetterRecord {
+        id: row.try_get("id")?,
+        letter: DeadLetter {
+            tenant_id: row.try_get("tenant_id")?,
+            proxy_id: row.try_get("proxy_id")?,
+            leaf_hash: row.try_get("leaf_hash")?,
+            metadata: row.try_get("metadata")?,
+            timestamp_ns: row.try_get("timestamp_ns")?,
+            stage: row.try_get("stage")?,
+            error: row.try_get("error")?,
+            retryable: row.try_get("retryable")?,
+            attempts: row.try_get("attempts")?,
+        },
+        created_at: row.try_get("created_at")?,
+        replayed_at: row.try_get("replayed_at")?,
+    })
+}
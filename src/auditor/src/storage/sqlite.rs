@@ -0,0 +1,666 @@
+use std::time::Duration;
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use sqlx::{QueryBuilder, Row, Sqlite, SqlitePool};
+use sqlx::sqlite::{SqliteJournalMode, SqlitePoolOptions, SqliteRow};
+use anyhow::{anyhow, bail, Result};
+use crate::config::DatabaseConfig;
+use crate::metrics;
+use super::{
+    DeadLetter, DeadLetterRecord, OutboxEvent, PendingRecord, ReceiptCursor, ReceiptFilter, ReceiptRecord,
+    RevocationRecord, Storage, STATUS_INTEGRATED, STATUS_PENDING,
+};
+
+const DEAD_LETTER_COLUMNS: &str =
+    "id, tenant_id, proxy_id, leaf_hash, metadata, timestamp_ns, stage, error, retryable, attempts, created_at, replayed_at";
+
+const RECEIPT_COLUMNS: &str =
+    "tenant_id, log_id, proxy_id, leaf_hash, leaf_index, root_hash, context, receipt_jwt, status, created_at";
+
+/// Receipts in a single SQLite file, for development and edge deployments
+/// without a Postgres server. Timestamps are stored as microseconds since
+/// the epoch and contexts as JSON text.
+pub struct SqliteStorage {
+    pool: SqlitePool,
+}
+
+impl SqliteStorage {
+    pub async fn new(cfg: &DatabaseConfig) -> Result<Self> {
+        let options = cfg.sqlite_connect_options()?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(Duration::from_secs(5));
+        let pool = SqlitePoolOptions::new()
+            .max_connections(4)
+            .connect_with(options)
+            .await?;
+        sqlx::migrate!("./migrations-sqlite")
+            .run(&pool)
+            .await?;
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn ping(&self) -> Result<()> {
+        metrics::timed("sqlite", "ping", sqlx::query("SELECT 1").execute(&self.pool)).await?;
+        Ok(())
+    }
+
+    async fn store_pending_receipt(
+        &self,
+        tenant_id: &str,
+        log_id: i64,
+        proxy_id: &str,
+        leaf_hash: &[u8],
+        metadata: &[u8],
+        promise_jwt: &str,
+    ) -> Result<bool> {
+        let context: serde_json::Value = serde_json::from_slice(metadata)?;
+        let query = sqlx::query(
+            r#"
+            INSERT INTO receipts (tenant_id, log_id, proxy_id, leaf_hash, context, receipt_jwt, status, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (tenant_id, leaf_hash) DO NOTHING
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(log_id)
+        .bind(proxy_id)
+        .bind(leaf_hash)
+        .bind(context.to_string())
+        .bind(promise_jwt)
+        .bind(STATUS_PENDING)
+        .bind(Utc::now().timestamp_micros())
+        .execute(&self.pool);
+        let result = metrics::timed("sqlite", "store_pending_receipt", query).await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn pending_receipts(&self, limit: i64) -> Result<Vec<PendingRecord>> {
+        let query = sqlx::query(
+            r#"
+            SELECT tenant_id, log_id, leaf_hash, context, created_at, integrate_attempts
+            FROM receipts
+            WHERE status = ? AND COALESCE(next_integrate_at, created_at) <= ?
+            ORDER BY COALESCE(next_integrate_at, created_at)
+            LIMIT ?
+            "#,
+        )
+        .bind(STATUS_PENDING)
+        .bind(Utc::now().timestamp_micros())
+        .bind(limit)
+        .fetch_all(&self.pool);
+        let rows = metrics::timed("sqlite", "pending_receipts", query).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(PendingRecord {
+                    tenant_id: row.try_get("tenant_id")?,
+                    log_id: row.try_get("log_id")?,
+                    leaf_hash: row.try_get("leaf_hash")?,
+                    context: serde_json::from_str(row.try_get("context")?)?,
+                    created_at: from_micros(row.try_get("created_at")?)?,
+                    attempts: row.try_get("integrate_attempts")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn defer_pending(&self, tenant_id: &str, leaf_hash: &[u8], retry_at: DateTime<Utc>) -> Result<()> {
+        let query = sqlx::query(
+            r#"
+            UPDATE receipts
+            SET integrate_attempts = integrate_attempts + 1, next_integrate_at = ?
+            WHERE tenant_id = ? AND leaf_hash = ? AND status = ?
+            "#,
+        )
+        .bind(retry_at.timestamp_micros())
+        .bind(tenant_id)
+        .bind(leaf_hash)
+        .bind(STATUS_PENDING)
+        .execute(&self.pool);
+        metrics::timed("sqlite", "defer_pending", query).await?;
+        Ok(())
+    }
+
+    async fn mark_integrated(
+        &self,
+        tenant_id: &str,
+        log_id: i64,
+        leaf_hash: &[u8],
+        leaf_index: i64,
+        root_hash: &[u8],
+        receipt_jwt: &str,
+    ) -> Result<()> {
+        let integrate = async {
+            let mut tx = self.pool.begin().await?;
+            let updated = sqlx::query(
+                r#"
+                UPDATE receipts
+                SET log_id = ?, leaf_index = ?, root_hash = ?, receipt_jwt = ?, status = ?, integrated_at = ?
+                WHERE tenant_id = ? AND leaf_hash = ? AND status = ?
+                "#,
+            )
+            .bind(log_id)
+            .bind(leaf_index)
+            .bind(root_hash)
+            .bind(receipt_jwt)
+            .bind(STATUS_INTEGRATED)
+            .bind(Utc::now().timestamp_micros())
+            .bind(tenant_id)
+            .bind(leaf_hash)
+            .bind(STATUS_PENDING)
+            .execute(&mut *tx)
+            .await?;
+            // Only the upgrade that actually happened announces the receipt
+            if updated.rows_affected() == 1 {
+                sqlx::query(
+                    r#"
+                    INSERT INTO event_outbox (tenant_id, log_id, leaf_hash, leaf_index, receipt_jwt, created_at)
+                    VALUES (?, ?, ?, ?, ?, ?)
+                    "#,
+                )
+                .bind(tenant_id)
+                .bind(log_id)
+                .bind(leaf_hash)
+                .bind(leaf_index)
+                .bind(receipt_jwt)
+                .bind(Utc::now().timestamp_micros())
+                .execute(&mut *tx)
+                .await?;
+            }
+            tx.commit().await
+        };
+        metrics::timed("sqlite", "mark_integrated", integrate).await?;
+        Ok(())
+    }
+
+    async fn get_receipt(&self, tenant_id: &str, leaf_hash: &[u8]) -> Result<Option<ReceiptRecord>> {
+        let sql = format!("SELECT {} FROM receipts WHERE tenant_id = ? AND leaf_hash = ?", RECEIPT_COLUMNS);
+        let query = sqlx::query(&sql)
+            .bind(tenant_id)
+            .bind(leaf_hash)
+            .fetch_optional(&self.pool);
+        let row = metrics::timed("sqlite", "get_receipt", query).await?;
+        row.as_ref().map(receipt_record).transpose()
+    }
+
+    async fn get_receipts(&self, tenant_id: &str, leaf_hashes: &[Vec<u8>]) -> Result<Vec<ReceiptRecord>> {
+        if leaf_hashes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut builder = QueryBuilder::<Sqlite>::new(format!("SELECT {} FROM receipts WHERE tenant_id = ", RECEIPT_COLUMNS));
+        builder.push_bind(tenant_id).push(" AND leaf_hash IN (");
+        let mut hashes = builder.separated(", ");
+        for leaf_hash in leaf_hashes {
+            hashes.push_bind(leaf_hash.as_slice());
+        }
+        builder.push(")");
+        let rows = metrics::timed("sqlite", "get_receipts", builder.build().fetch_all(&self.pool)).await?;
+        rows.iter().map(receipt_record).collect()
+    }
+
+    async fn list_receipts(
+        &self,
+        tenant_id: &str,
+        filter: &ReceiptFilter,
+        after: Option<&ReceiptCursor>,
+        limit: i64,
+    ) -> Result<Vec<ReceiptRecord>> {
+        let mut builder = QueryBuilder::<Sqlite>::new(format!("SELECT {} FROM receipts WHERE tenant_id = ", RECEIPT_COLUMNS));
+        builder.push_bind(tenant_id);
+        if let Some(created_after) = filter.created_after {
+            builder.push(" AND created_at >= ").push_bind(created_after.timestamp_micros());
+        }
+        if let Some(created_before) = filter.created_before {
+            builder.push(" AND created_at < ").push_bind(created_before.timestamp_micros());
+        }
+        if let Some(model_id) = &filter.model_id {
+            builder.push(" AND json_extract(context, '$.request_headers.\"x-model-id\"') = ").push_bind(model_id.as_str());
+        }
+        if let Some(proxy_id) = &filter.proxy_id {
+            builder.push(" AND proxy_id = ").push_bind(proxy_id.as_str());
+        }
+        if let Some(context) = &filter.context {
+            push_contains(&mut builder, "$", context)?;
+        }
+        if let Some(cursor) = after {
+            builder.push(" AND (created_at, leaf_hash) > (")
+                .push_bind(cursor.created_at.timestamp_micros())
+                .push(", ")
+                .push_bind(cursor.leaf_hash.as_slice())
+                .push(")");
+        }
+        builder.push(" ORDER BY created_at, leaf_hash LIMIT ").push_bind(limit);
+        let rows = metrics::timed("sqlite", "list_receipts", builder.build().fetch_all(&self.pool)).await?;
+        rows.iter().map(receipt_record).collect()
+    }
+
+    async fn merkle_leaves(&self, log_id: i64) -> Result<Vec<Vec<u8>>> {
+        let query = sqlx::query_scalar(
+            r#"
+            SELECT leaf_value
+            FROM merkle_leaves
+            WHERE log_id = ?
+            ORDER BY leaf_index
+            "#,
+        )
+        .bind(log_id)
+        .fetch_all(&self.pool);
+        Ok(metrics::timed("sqlite", "merkle_leaves", query).await?)
+    }
+
+    async fn append_merkle_leaves(&self, log_id: i64, start_index: i64, leaf_values: &[Vec<u8>]) -> Result<()> {
+        let append = async {
+            let mut tx = self.pool.begin().await?;
+            for (i, leaf_value) in leaf_values.iter().enumerate() {
+                sqlx::query("INSERT INTO merkle_leaves (log_id, leaf_index, leaf_value) VALUES (?, ?, ?)")
+                    .bind(log_id)
+                    .bind(start_index + i as i64)
+                    .bind(leaf_value.as_slice())
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            tx.commit().await
+        };
+        metrics::timed("sqlite", "append_merkle_leaves", append).await?;
+        Ok(())
+    }
+
+    async fn undelivered_events(&self, sink: &str, limit: i64) -> Result<Vec<OutboxEvent>> {
+        let query = sqlx::query(
+            r#"
+            SELECT id, tenant_id, log_id, leaf_hash, leaf_index, receipt_jwt
+            FROM (
+                SELECT o.*, ROW_NUMBER() OVER (PARTITION BY o.log_id ORDER BY o.leaf_index) AS position
+                FROM event_outbox o
+                WHERE o.delivered_at IS NULL
+                  AND NOT EXISTS (SELECT 1 FROM event_deliveries d WHERE d.event_id = o.id AND d.sink = ?)
+            )
+            WHERE position <= ?
+            ORDER BY log_id, leaf_index
+            "#,
+        )
+        .bind(sink)
+        .bind(limit)
+        .fetch_all(&self.pool);
+        let rows = metrics::timed("sqlite", "undelivered_events", query).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(OutboxEvent {
+                    id: row.try_get("id")?,
+                    tenant_id: row.try_get("tenant_id")?,
+                    log_id: row.try_get("log_id")?,
+                    leaf_hash: row.try_get("leaf_hash")?,
+                    leaf_index: row.try_get("leaf_index")?,
+                    receipt_jwt: row.try_get("receipt_jwt")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn mark_delivered(&self, sink: &str, ids: &[i64]) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let delivered_at = Utc::now().timestamp_micros();
+        let mut builder = QueryBuilder::<Sqlite>::new("INSERT INTO event_deliveries (event_id, sink, delivered_at) ");
+        builder.push_values(ids, |mut values, id| {
+            values.push_bind(*id).push_bind(sink).push_bind(delivered_at);
+        });
+        builder.push(" ON CONFLICT (event_id, sink) DO NOTHING");
+        metrics::timed("sqlite", "mark_delivered", builder.build().execute(&self.pool)).await?;
+        Ok(())
+    }
+
+    async fn complete_deliveries(&self, sinks: &[&str]) -> Result<()> {
+        let mut builder = QueryBuilder::<Sqlite>::new("UPDATE event_outbox SET delivered_at = ");
+        builder.push_bind(Utc::now().timestamp_micros()).push(" WHERE delivered_at IS NULL");
+        for sink in sinks {
+            builder.push(" AND EXISTS (SELECT 1 FROM event_deliveries d WHERE d.event_id = event_outbox.id AND d.sink = ")
+                .push_bind(*sink)
+                .push(")");
+        }
+        metrics::timed("sqlite", "complete_deliveries", builder.build().execute(&self.pool)).await?;
+        Ok(())
+    }
+
+    async fn prune_delivered_events(&self, before: DateTime<Utc>) -> Result<u64> {
+        let query = sqlx::query("DELETE FROM event_outbox WHERE delivered_at < ?")
+            .bind(before.timestamp_micros())
+            .execute(&self.pool);
+        let result = metrics::timed("sqlite", "prune_delivered_events", query).await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn store_revocation(&self, revocation: &RevocationRecord) -> Result<bool> {
+        let query = sqlx::query(
+            r#"
+            INSERT INTO revocations (tenant_id, leaf_hash, log_id, reason, actor, statement, revocation_leaf_hash, revoked_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (tenant_id, leaf_hash) DO NOTHING
+            "#,
+        )
+        .bind(&revocation.tenant_id)
+        .bind(&revocation.leaf_hash)
+        .bind(revocation.log_id)
+        .bind(&revocation.reason)
+        .bind(&revocation.actor)
+        .bind(&revocation.statement)
+        .bind(&revocation.revocation_leaf_hash)
+        .bind(revocation.revoked_at.timestamp_micros())
+        .execute(&self.pool);
+        let result = metrics::timed("sqlite", "store_revocation", query).await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn get_revocation(&self, tenant_id: &str, leaf_hash: &[u8]) -> Result<Option<RevocationRecord>> {
+        let query = sqlx::query(
+            r#"
+            SELECT tenant_id, leaf_hash, log_id, reason, actor, statement, revocation_leaf_hash, revoked_at
+            FROM revocations
+            WHERE tenant_id = ? AND leaf_hash = ?
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(leaf_hash)
+        .fetch_optional(&self.pool);
+        let row = metrics::timed("sqlite", "get_revocation", query).await?;
+        row.map(|row| {
+            Ok(RevocationRecord {
+                tenant_id: row.try_get("tenant_id")?,
+                leaf_hash: row.try_get("leaf_hash")?,
+                log_id: row.try_get("log_id")?,
+                reason: row.try_get("reason")?,
+                actor: row.try_get("actor")?,
+                statement: row.try_get("statement")?,
+                revocation_leaf_hash: row.try_get("revocation_leaf_hash")?,
+                revoked_at: from_micros(row.try_get("revoked_at")?)?,
+            })
+        })
+        .transpose()
+    }
+
+    async fn store_dead_letter(&self, letter: &DeadLetter) -> Result<i64> {
+        let query = sqlx::query_scalar(
+            r#"
+            UPDATE dead_letters
+            SET stage = ?, error = ?, retryable = ?, attempts = attempts + ?
+            WHERE id = (
+                SELECT id FROM dead_letters
+                WHERE tenant_id = ? AND leaf_hash = ? AND replayed_at IS NULL
+                ORDER BY id
+                LIMIT 1
+            )
+            RETURNING id
+            "#,
+        )
+        .bind(&letter.stage)
+        .bind(&letter.error)
+        .bind(letter.retryable)
+        .bind(letter.attempts)
+        .bind(&letter.tenant_id)
+        .bind(&letter.leaf_hash)
+        .fetch_optional(&self.pool);
+        if let Some(id) = metrics::timed("sqlite", "update_dead_letter", query).await? {
+            return Ok(id);
+        }
+
+        let query = sqlx::query_scalar(
+            r#"
+            INSERT INTO dead_letters (tenant_id, proxy_id, leaf_hash, metadata, timestamp_ns, stage, error, retryable, attempts, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING id
+            "#,
+        )
+        .bind(&letter.tenant_id)
+        .bind(&letter.proxy_id)
+        .bind(&letter.leaf_hash)
+        .bind(&letter.metadata)
+        .bind(letter.timestamp_ns)
+        .bind(&letter.stage)
+        .bind(&letter.error)
+        .bind(letter.retryable)
+        .bind(letter.attempts)
+        .bind(Utc::now().timestamp_micros())
+        .fetch_one(&self.pool);
+        Ok(metrics::timed("sqlite", "store_dead_letter", query).await?)
+    }
+
+    async fn dead_letters(
+        &self,
+        tenant_id: Option<&str>,
+        include_replayed: bool,
+        after_id: i64,
+        limit: i64,
+    ) -> Result<Vec<DeadLetterRecord>> {
+        let mut builder = QueryBuilder::<Sqlite>::new(format!("SELECT {} FROM dead_letters WHERE id > ", DEAD_LETTER_COLUMNS));
+        builder.push_bind(after_id);
+        if let Some(tenant_id) = tenant_id {
+            builder.push(" AND tenant_id = ").push_bind(tenant_id);
+        }
+        if !include_replayed {
+            builder.push(" AND replayed_at IS NULL");
+        }
+        builder.push(" ORDER BY id LIMIT ").push_bind(limit);
+        let rows = metrics::timed("sqlite", "dead_letters", builder.build().fetch_all(&self.pool)).await?;
+        rows.iter().map(dead_letter_record).collect()
+    }
+
+    async fn get_dead_letter(&self, id: i64) -> Result<Option<DeadLetterRecord>> {
+        let sql = format!("SELECT {} FROM dead_letters WHERE id = ?", DEAD_LETTER_COLUMNS);
+        let query = sqlx::query(&sql)
+            .bind(id)
+            .fetch_optional(&self.pool);
+        let row = metrics::timed("sqlite", "get_dead_letter", query).await?;
+        row.as_ref().map(dead_letter_record).transpose()
+    }
+
+    async fn mark_replayed(&self, id: i64) -> Result<()> {
+        let query = sqlx::query("UPDATE dead_letters SET replayed_at = ? WHERE id = ?")
+            .bind(Utc::now().timestamp_micros())
+            .bind(id)
+            .execute(&self.pool);
+        metrics::timed("sqlite", "mark_replayed", query).await?;
+        Ok(())
+    }
+
+    async fn tenant_log_id(&self, tenant_id: &str) -> Result<Option<i64>> {
+        let query = sqlx::query_scalar("SELECT log_id FROM tenant_logs WHERE tenant_id = ?")
+            .bind(tenant_id)
+            .fetch_optional(&self.pool);
+        Ok(metrics::timed("sqlite", "tenant_log_id", query).await?)
+    }
+}
+
+fn receipt_record(row: &SqliteRow) -> Result<ReceiptRecord> {
+    Ok(ReceiptRecord {
+        tenant_id: row.try_get("tenant_id")?,
+        log_id: row.try_get("log_id")?,
+        proxy_id: row.try_get("proxy_id")?,
+        leaf_hash: row.try_get("leaf_hash")?,
+        leaf_index: row.try_get("leaf_index")?,
+        root_hash: row.try_get("root_hash")?,
+        context: serde_json::from_str(row.try_get("context")?)?,
+        receipt_jwt: row.try_get("receipt_jwt")?,
+        status: row.try_get("status")?,
+        created_at: from_micros(row.try_get("created_at")?)?,
+    })
+}
+
+fn dead_letter_record(row: &SqliteRow) -> Result<DeadLetterRecord> {
+    Ok(DeadLetterRecord {
+        id: row.try_get("id")?,
+        letter: DeadLetter {
+            tenant_id: row.try_get("tenant_id")?,
+            proxy_id: row.try_get("proxy_id")?,
+            leaf_hash: row.try_get("leaf_hash")?,
+            metadata: row.try_get("metadata")?,
+            timestamp_ns: row.try_get("timestamp_ns")?,
+            stage: row.try_get("stage")?,
+            error: row.try_get("error")?,
+            retryable: row.try_get("retryable")?,
+            attempts: row.try_get("attempts")?,
+        },
+        created_at: from_micros(row.try_get("created_at")?)?,
+        replayed_at: row.try_get::<Option<i64>, _>("replayed_at")?.map(from_micros).transpose()?,
+    })
+}
+
+fn from_micros(micros: i64) -> Result<DateTime<Utc>> {
+    Utc.timestamp_micros(micros)
+        .single()
+        .ok_or_else(|| anyhow!("Timestamp out of range: {}", micros))
+}
+
+/// Adds conditions matching Postgres' `context @> value` for the value at
+/// `path`: objects match key by key, so `{"a": {"b": 1}}` matches any
+/// context whose `a.b` is 1. Arrays must match exactly.
+fn push_contains(builder: &mut QueryBuilder<'_, Sqlite>, path: &str, value: &serde_json::Value) -> Result<()> {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, field) in fields {
+                // SQLite paths have no way to escape a quote within a key
+                if key.contains('"') {
+                    bail!("Context filter key {:?} contains '\"', which SQLite can't look up", key);
+                }
+                push_contains(builder, &format!("{}.\"{}\"", path, key), field)?;
+            }
+        }
+        scalar => {
+            // `->` yields the JSON text of the value, so strings, numbers,
+            // booleans, null and arrays all compare by their serialization.
+            builder.push(" AND context -> ").push_bind(path.to_string())
+                .push(" = json(").push_bind(scalar.to_string()).push(")");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    async fn storage() -> SqliteStorage {
+        SqliteStorage::new(&DatabaseConfig { url: "sqlite::memory:".to_string(), password: None })
+            .await
+            .unwrap()
+    }
+
+    async fn store(storage: &SqliteStorage, leaf_hash: u8, context: serde_json::Value) {
+        let metadata = serde_json::to_vec(&context).unwrap();
+        assert!(storage.store_pending_receipt("tenant", 1, "proxy", &[leaf_hash], &metadata, "promise").await.unwrap());
+    }
+
+    async fn list(storage: &SqliteStorage, filter: ReceiptFilter) -> Vec<u8> {
+        let receipts = storage.list_receipts("tenant", &filter, None, 100).await.unwrap();
+        let mut leaf_hashes: Vec<u8> = receipts.iter().map(|receipt| receipt.leaf_hash[0]).collect();
+        leaf_hashes.sort();
+        leaf_hashes
+    }
+
+    #[test]
+    fn test_push_contains_binds_each_scalar_path() {
+        let mut builder = QueryBuilder::<Sqlite>::new("SELECT 1 WHERE 1");
+        push_contains(&mut builder, "$", &json!({"a": {"b": 1, "c": [1, 2]}})).unwrap();
+        assert_eq!(builder.sql(), "SELECT 1 WHERE 1 AND context -> ? = json(?) AND context -> ? = json(?)");
+        assert!(push_contains(&mut builder, "$", &json!({"say \"hi\"": true})).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_receipts_filters_on_context() {
+        let storage = storage().await;
+        store(&storage, 1, json!({"request_headers": {"x-model-id": "gpt"}, "user": {"id": 7, "tags": ["a", "b"]}})).await;
+        store(&storage, 2, json!({"request_headers": {"x-model-id": "llama"}, "user": {"id": 7, "tags": ["a"]}})).await;
+        store(&storage, 3, json!({"user": {"id": "7", "name.with dots": true}})).await;
+
+        let context = |value| ReceiptFilter { context: Some(value), ..Default::default() };
+        assert_eq!(list(&storage, context(json!({}))).await, vec![1, 2, 3]);
+        assert_eq!(list(&storage, context(json!({"user": {"id": 7}}))).await, vec![1, 2]);
+        // Numbers and strings don't match each other
+        assert_eq!(list(&storage, context(json!({"user": {"id": "7"}}))).await, vec![3]);
+        assert_eq!(list(&storage, context(json!({"user": {"tags": ["a"]}}))).await, vec![2]);
+        assert_eq!(list(&storage, context(json!({"user": {"name.with dots": true}}))).await, vec![3]);
+        assert_eq!(list(&storage, context(json!({"user": {"missing": null}}))).await, Vec::<u8>::new());
+
+        let model = ReceiptFilter { model_id: Some("llama".to_string()), ..Default::default() };
+        assert_eq!(list(&storage, model).await, vec![2]);
+    }
+
+    #[tokio::test]
+    async fn test_list_receipts_pages_through_every_receipt_once() {
+        let storage = storage().await;
+        // Some receipts likely share a creation time; the leaf hash breaks ties
+        for leaf_hash in [5, 3, 9, 1, 7] {
+            store(&storage, leaf_hash, json!({})).await;
+        }
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = storage.list_receipts("tenant", &ReceiptFilter::default(), cursor.as_ref(), 2).await.unwrap();
+            assert!(page.len() <= 2);
+            let Some(last) = page.last() else { break };
+            cursor = Some(ReceiptCursor { created_at: last.created_at, leaf_hash: last.leaf_hash.clone() });
+            seen.extend(page.iter().map(|receipt| (receipt.created_at, receipt.leaf_hash[0])));
+        }
+        let mut sorted = seen.clone();
+        sorted.sort();
+        assert_eq!(seen, sorted);
+        assert_eq!(seen.len(), 5);
+        assert!(storage.list_receipts("other", &ReceiptFilter::default(), None, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_outbox_tracks_delivery_per_sink() {
+        let storage = storage().await;
+        for leaf_hash in 1..=3 {
+            store(&storage, leaf_hash, json!({})).await;
+        }
+        storage.mark_integrated("tenant", 2, &[3], 1, &[0; 32], "receipt-3").await.unwrap();
+        storage.mark_integrated("tenant", 1, &[2], 0, &[0; 32], "receipt-2").await.unwrap();
+        storage.mark_integrated("tenant", 2, &[1], 0, &[0; 32], "receipt-1").await.unwrap();
+        // Integrating again doesn't announce the receipt twice
+        storage.mark_integrated("tenant", 2, &[1], 0, &[0; 32], "receipt-1").await.unwrap();
+
+        let events = storage.undelivered_events("kafka", 10).await.unwrap();
+        let order: Vec<(i64, i64)> = events.iter().map(|event| (event.log_id, event.leaf_index)).collect();
+        assert_eq!(order, vec![(1, 0), (2, 0), (2, 1)]);
+        assert_eq!(events[1].receipt_jwt, "receipt-1");
+
+        storage.mark_delivered("kafka", &[events[0].id, events[1].id]).await.unwrap();
+        storage.mark_delivered("kafka", &[events[0].id]).await.unwrap();
+        let kafka = storage.undelivered_events("kafka", 10).await.unwrap();
+        assert_eq!(kafka.len(), 1);
+        assert_eq!(kafka[0].leaf_hash, vec![3]);
+        assert_eq!(storage.undelivered_events("webhook", 10).await.unwrap().len(), 3);
+
+        // Only events every sink has published are complete
+        storage.mark_delivered("webhook", &[events[0].id]).await.unwrap();
+        storage.complete_deliveries(&["kafka", "webhook"]).await.unwrap();
+        assert_eq!(storage.undelivered_events("webhook", 10).await.unwrap().len(), 2);
+        assert_eq!(storage.prune_delivered_events(Utc::now() - chrono::Duration::hours(1)).await.unwrap(), 0);
+        assert_eq!(storage.prune_delivered_events(Utc::now() + chrono::Duration::hours(1)).await.unwrap(), 1);
+        let deliveries: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM event_deliveries").fetch_one(&storage.pool).await.unwrap();
+        assert_eq!(deliveries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_undelivered_events_are_windowed_per_log() {
+        let storage = storage().await;
+        for leaf_hash in 1..=6 {
+            store(&storage, leaf_hash, json!({})).await;
+        }
+        for leaf_hash in 1..=5 {
+            storage.mark_integrated("tenant", 1, &[leaf_hash], leaf_hash as i64, &[0; 32], "receipt").await.unwrap();
+        }
+        storage.mark_integrated("tenant", 2, &[6], 0, &[0; 32], "receipt").await.unwrap();
+
+        // A backlog in log 1 doesn't keep log 2's event out of the batch
+        let events = storage.undelivered_events("kafka", 2).await.unwrap();
+        let order: Vec<(i64, i64)> = events.iter().map(|event| (event.log_id, event.leaf_index)).collect();
+        assert_eq!(order, vec![(1, 1), (1, 2), (2, 0)]);
+    }
+}
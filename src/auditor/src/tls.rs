@@ -0,0 +1,131 @@
+use std::sync::Arc;
+use anyhow::{anyhow, Context, Result};
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::server::AllowAnyAnonymousOrAuthenticatedClient;
+use rustls_pemfile::Item;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+use tonic::{Request, Status};
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+use crate::config::TlsConfig;
+
+pub fn server_tls_config(cfg: &TlsConfig) -> Result<ServerTlsConfig> {
+    let cert = std::fs::read(&cfg.cert_path)
+        .with_context(|| format!("Reading TLS certificate {}", cfg.cert_path))?;
+    let key = std::fs::read(&cfg.key_path)
+        .with_context(|| format!("Reading TLS key {}", cfg.key_path))?;
+    let mut tls = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+    if let Some(ca_path) = &cfg.client_ca_path {
+        let ca = std::fs::read(ca_path)
+            .with_context(|| format!("Reading client CA {}", ca_path))?;
+        // With a client CA configured, every connection must present a
+        // certificate issued by it.
+        tls = tls.client_ca_root(Certificate::from_pem(ca));
+    }
+    Ok(tls)
+}
+
+/// TLS for the REST gateway, with the gRPC server's certificate. With a
+/// client CA configured, certificates issued by it are verified so proxies
+/// can submit under the names they carry; callers that only read receipts
+/// may still connect without one and authenticate by API key.
+pub async fn gateway_tls_config(cfg: &TlsConfig) -> Result<RustlsConfig> {
+    let cert = std::fs::read(&cfg.cert_path)
+        .with_context(|| format!("Reading TLS certificate {}", cfg.cert_path))?;
+    let key = std::fs::read(&cfg.key_path)
+        .with_context(|| format!("Reading TLS key {}", cfg.key_path))?;
+    let certs = rustls_pemfile::certs(&mut cert.as_slice())?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+    let key = match rustls_pemfile::read_one(&mut key.as_slice())? {
+        Some(Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key)) => rustls::PrivateKey(key),
+        _ => return Err(anyhow!("Unsupported TLS key format in {}", cfg.key_path)),
+    };
+
+    let builder = rustls::ServerConfig::builder().with_safe_defaults();
+    let builder = match &cfg.client_ca_path {
+        Some(ca_path) => {
+            let ca = std::fs::read(ca_path)
+                .with_context(|| format!("Reading client CA {}", ca_path))?;
+            let mut roots = rustls::RootCertStore::empty();
+            for ca in rustls_pemfile::certs(&mut ca.as_slice())? {
+                roots.add(&rustls::Certificate(ca))?;
+            }
+            builder.with_client_cert_verifier(AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed())
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_single_cert(certs, key)
+        .with_context(|| format!("Loading TLS certificate {} for the REST gateway", cfg.cert_path))?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(RustlsConfig::from_config(Arc::new(config)))
+}
+
+/// The names a client certificate vouches for: its DNS and URI subject
+/// alternative names and its subject common name. A proxy may only submit
+/// under a `proxy_id` that is one of these names.
+#[derive(Debug, Clone)]
+pub struct PeerIdentity {
+    names: Vec<String>,
+}
+
+impl PeerIdentity {
+    /// Reads the identity from the verified client certificate of the
+    /// connection, if the client presented one.
+    pub fn from_request<T>(request: &Request<T>) -> Result<Option<Self>, Status> {
+        let certs = match request.peer_certs() {
+            Some(certs) => certs,
+            None => return Ok(None),
+        };
+        let leaf = certs.first()
+            .ok_or_else(|| Status::unauthenticated("Empty client certificate chain"))?;
+        let identity = Self::from_certificate(leaf.get_ref())
+            .map_err(|e| Status::unauthenticated(format!("Invalid client certificate: {}", e)))?;
+        Ok(Some(identity))
+    }
+
+    /// Reads the identity from a verified DER client certificate.
+    pub fn from_certificate(der: &[u8]) -> Result<Self> {
+        Ok(Self { names: certificate_names(der)? })
+    }
+
+    pub fn authorizes(&self, proxy_id: &str) -> bool {
+        self.names.iter().any(|name| name == proxy_id)
+    }
+
+    pub fn check(&self, proxy_id: &str) -> Result<(), Status> {
+        if self.authorizes(proxy_id) {
+            Ok(())
+        } else {
+            Err(Status::permission_denied(format!(
+                "Client certificate does not cover proxy {}",
+                proxy_id
+            )))
+        }
+    }
+}
+
+fn certificate_names(der: &[u8]) -> Result<Vec<String>> {
+    let (_, cert) = X509Certificate::from_der(der)
+        .map_err(|e| anyhow!("{}", e))?;
+    let mut names = Vec::new();
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            match name {
+                GeneralName::DNSName(dns) => names.push(dns.to_string()),
+                GeneralName::URI(uri) => names.push(uri.to_string()),
+                _ => {}
+            }
+        }
+    }
+    for cn in cert.subject().iter_common_name() {
+        if let Ok(cn) = cn.as_str() {
+            names.push(cn.to_string());
+        }
+    }
+    if names.is_empty() {
+        return Err(anyhow!("certificate carries no usable name"));
+    }
+    Ok(names)
+}
@@ -0,0 +1,68 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+/// Outcome of adding a leaf to the log.
+#[derive(Debug, Clone, Copy)]
+pub struct QueuedLeaf {
+    pub leaf_index: i64,
+    /// The log already held this leaf; `leaf_index` is the existing entry's.
+    pub duplicate: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SignedRoot {
+    pub root_hash: Vec<u8>,
+    pub tree_size: i64,
+    pub timestamp_nanos: u64,
+    /// The TLS-serialized `LogRootV1`.
+    pub log_root: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct InclusionProof {
+    pub leaf_index: i64,
+    pub hashes: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Leaf {
+    pub leaf_index: i64,
+    pub leaf_value: Vec<u8>,
+    pub merkle_leaf_hash: Vec<u8>,
+}
+
+/// An append-only RFC 6962 log holding one tree per `log_id`. Leaf values
+/// are the submitted hashes; trees hash them as Merkle leaves.
+#[async_trait]
+pub trait TransparencyLog: Send + Sync {
+    /// Adds leaves to a tree, returning the outcome (or the error) for each
+    /// input in the same order. Leaves need not be integrated on return.
+    async fn append(&self, log_id: i64, leaf_values: &[Vec<u8>]) -> Result<Vec<Result<QueuedLeaf>>>;
+
+    async fn latest_root(&self, log_id: i64) -> Result<SignedRoot>;
+
+    /// Proof for a leaf value in the tree of the given size, or `None` while
+    /// the leaf is not integrated at that size.
+    async fn inclusion_proof(&self, log_id: i64, leaf_value: &[u8], tree_size: i64) -> Result<Option<InclusionProof>>;
+
+    async fn consistency_proof(&self, log_id: i64, first_tree_size: i64, second_tree_size: i64) -> Result<Vec<Vec<u8>>>;
+
+    /// Up to `count` leaves starting at `start_index`; fewer if the range
+    /// runs past the end of the tree.
+    async fn leaves_by_range(&self, log_id: i64, start_index: i64, count: i64) -> Result<Vec<Leaf>>;
+}
+
+/// TLS-serializes a `LogRootV1` the way Trillian does: version(2) +
+/// tree_size(8) + hash_len(1) + hash + timestamp_nanos(8) + revision(8) +
+/// metadata_len(2).
+pub fn encode_log_root(tree_size: i64, root_hash: &[u8], timestamp_nanos: u64, revision: u64) -> Vec<u8> {
+    let mut log_root = Vec::with_capacity(29 + root_hash.len());
+    log_root.extend_from_slice(&1u16.to_be_bytes());
+    log_root.extend_from_slice(&(tree_size as u64).to_be_bytes());
+    log_root.push(root_hash.len() as u8);
+    log_root.extend_from_slice(root_hash);
+    log_root.extend_from_slice(&timestamp_nanos.to_be_bytes());
+    log_root.extend_from_slice(&revision.to_be_bytes());
+    log_root.extend_from_slice(&0u16.to_be_bytes());
+    log_root
+}
@@ -1,18 +1,50 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
 use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use tokio::sync::Mutex;
 use tonic::transport::Channel;
 use crate::config::TrillianConfig;
+use crate::metrics;
+use crate::translog::{InclusionProof, Leaf, QueuedLeaf, SignedRoot, TransparencyLog};
+use verillm_shared_utils::merkle;
 
 include!(concat!(env!("OUT_DIR"), "/trillian.rs"));
 
+/// Client for the Trillian log server. One server hosts a tree per tenant,
+/// so every call names the tree (`log_id`) it targets.
 pub struct TrillianClient {
     client: trillian_log_client::TrillianLogClient<Channel>,
-    log_id: i64,
+    preordered: bool,
+    // One per pre-ordered log, each held across its own write, so a slow
+    // tree only holds up the leaves bound for it
+    sequencers: Mutex<HashMap<i64, Arc<Mutex<Sequencer>>>>,
 }
 
-#[derive(Debug, Clone)]
-pub struct SignedRoot {
-    pub root_hash: Vec<u8>,
-    pub tree_size: i64,
+/// Next leaf index to assign in a pre-ordered log, loaded from the latest
+/// root on first use. Sequenced leaves only count towards the tree size once
+/// they are integrated, so after a failed write the counter is re-synced
+/// with the log but never moved back over indices already handed out.
+#[derive(Default)]
+struct Sequencer {
+    index: i64,
+    /// False until loaded, and again once a write failed or left indices
+    /// unused.
+    synced: bool,
+}
+
+impl Sequencer {
+    async fn start(&self, tree_size: impl Future<Output = Result<i64>>) -> Result<i64> {
+        if self.synced {
+            return Ok(self.index);
+        }
+        Ok(self.index.max(tree_size.await?))
+    }
+
+    fn advance(&mut self, index: i64, synced: bool) {
+        *self = Sequencer { index, synced };
+    }
 }
 
 impl TrillianClient {
@@ -23,11 +55,12 @@ impl TrillianClient {
         let client = trillian_log_client::TrillianLogClient::new(channel);
         Ok(Self {
             client,
-            log_id: cfg.log_id,
+            preordered: cfg.preordered,
+            sequencers: Mutex::new(HashMap::new()),
         })
     }
 
-    pub async fn queue_leaf(&self, leaf_hash: &[u8]) -> Result<i64> {
+    pub async fn queue_leaf(&self, log_id: i64, leaf_hash: &[u8]) -> Result<QueuedLeaf> {
         let leaf = LogLeaf {
             leaf_value: leaf_hash.to_vec(),
             extra_data: vec![],
@@ -35,78 +68,110 @@ impl TrillianClient {
             ..Default::default()
         };
         let request = QueueLeafRequest {
-            log_id: self.log_id,
+            log_id,
             leaf: Some(leaf),
             charge_to: None,
         };
-        let response = self.client
-            .clone()
-            .queue_leaf(request)
+        let mut client = self.client.clone();
+        let response = metrics::timed("trillian", "queue_leaf", client.queue_leaf(request))
             .await?
             .into_inner();
         match response.queued_leaf {
-            Some(ql) => {
-                if let Some(leaf) = ql.leaf.as_ref() {
-                    Ok(leaf.leaf_index)
-                } else {
-                    Err(anyhow!("Queued leaf missing leaf data"))
-                }
-            }
+            Some(ql) => queued_leaf(ql),
             None => Err(anyhow!("Leaf not queued")),
         }
     }
 
-    pub async fn get_current_root(&self) -> Result<SignedRoot> {
+    /// Adds a batch of leaves to the log, returning the outcome (or the
+    /// error) for each input in the same order. Normal logs have no batch
+    /// queueing RPC, so the `QueueLeaf` calls are issued concurrently over
+    /// the shared channel; pre-ordered logs take the whole batch in a single
+    /// `AddSequencedLeaves` call.
+    pub async fn queue_leaves(&self, log_id: i64, leaf_hashes: &[Vec<u8>]) -> Result<Vec<Result<QueuedLeaf>>> {
+        if self.preordered {
+            return self.add_sequenced_leaves(log_id, leaf_hashes).await;
+        }
+        let queued = leaf_hashes.iter().map(|hash| self.queue_leaf(log_id, hash));
+        Ok(futures::future::join_all(queued).await)
+    }
+
+    async fn add_sequenced_leaves(&self, log_id: i64, leaf_hashes: &[Vec<u8>]) -> Result<Vec<Result<QueuedLeaf>>> {
+        let sequencer = self.sequencers.lock().await.entry(log_id).or_default().clone();
+        let mut next_index = sequencer.lock().await;
+        let tree_size = async { Ok(self.get_current_root(log_id).await?.tree_size) };
+        let start = next_index.start(tree_size).await?;
+        let leaves = leaf_hashes.iter()
+            .enumerate()
+            .map(|(i, hash)| LogLeaf {
+                leaf_value: hash.clone(),
+                leaf_index: start + i as i64,
+                ..Default::default()
+            })
+            .collect();
+        let request = AddSequencedLeavesRequest {
+            log_id,
+            leaves,
+            charge_to: None,
+        };
+        let mut client = self.client.clone();
+        let response = match metrics::timed("trillian", "add_sequenced_leaves", client.add_sequenced_leaves(request)).await {
+            Ok(response) => response.into_inner(),
+            Err(e) => {
+                // Someone else may have written to the log; re-read the size next time
+                next_index.advance(start, false);
+                return Err(e.into());
+            }
+        };
+        if response.results.len() != leaf_hashes.len() {
+            next_index.advance(start, false);
+            return Err(anyhow!(
+                "AddSequencedLeaves returned {} results for {} leaves",
+                response.results.len(),
+                leaf_hashes.len()
+            ));
+        }
+        let results: Vec<Result<QueuedLeaf>> = response.results.into_iter().map(queued_leaf).collect();
+        // A duplicate did not take the index it was offered either
+        if results.iter().all(|r| matches!(r, Ok(leaf) if !leaf.duplicate)) {
+            next_index.advance(start + leaf_hashes.len() as i64, true);
+        } else {
+            let taken = results.iter()
+                .enumerate()
+                .filter(|(_, r)| matches!(r, Ok(leaf) if !leaf.duplicate))
+                .map(|(i, _)| start + i as i64 + 1)
+                .max();
+            next_index.advance(taken.unwrap_or(start), false);
+        }
+        Ok(results)
+    }
+
+    pub async fn get_current_root(&self, log_id: i64) -> Result<SignedRoot> {
         let request = GetLatestSignedLogRootRequest {
-            log_id: self.log_id,
+            log_id,
             charge_to: None,
             first_tree_size: 0,
         };
-        let response = self.client
-            .clone()
-            .get_latest_signed_log_root(request)
+        let mut client = self.client.clone();
+        let response = metrics::timed("trillian", "get_latest_signed_log_root", client.get_latest_signed_log_root(request))
             .await?
             .into_inner();
-        match response.signed_log_root {
-            Some(slr) => {
-                // Parse the TLS-serialized log_root
-                let log_root_bytes = &slr.log_root;
-                if log_root_bytes.len() < 10 {
-                    return Err(anyhow!("Invalid log root: too short"));
-                }
-                
-                // Parse TLS format: version(2) + tree_size(8) + hash_len(1) + hash + ...
-                let tree_size = i64::from_be_bytes([
-                    log_root_bytes[2], log_root_bytes[3], log_root_bytes[4], log_root_bytes[5],
-                    log_root_bytes[6], log_root_bytes[7], log_root_bytes[8], log_root_bytes[9],
-                ]);
-                
-                let hash_len = log_root_bytes[10] as usize;
-                if log_root_bytes.len() < 11 + hash_len {
-                    return Err(anyhow!("Invalid log root: hash too short"));
-                }
-                
-                let root_hash = log_root_bytes[11..11 + hash_len].to_vec();
-                
-                Ok(SignedRoot {
-                    root_hash,
-                    tree_size,
-                })
-            }
-            None => Err(anyhow!("No root available")),
-        }
+        let root = match response.signed_log_root {
+            Some(slr) => parse_log_root(slr.log_root)?,
+            None => return Err(anyhow!("No root available")),
+        };
+        metrics::TREE_SIZE.with_label_values(&[&log_id.to_string()]).set(root.tree_size);
+        Ok(root)
     }
 
-    pub async fn get_inclusion_proof(&self, leaf_index: i64, tree_size: i64) -> Result<Vec<Vec<u8>>> {
+    pub async fn get_inclusion_proof(&self, log_id: i64, leaf_index: i64, tree_size: i64) -> Result<Vec<Vec<u8>>> {
         let request = GetInclusionProofRequest {
-            log_id: self.log_id,
+            log_id,
             leaf_index,
             tree_size,
             charge_to: None,
         };
-        let response = self.client
-            .clone()
-            .get_inclusion_proof(request)
+        let mut client = self.client.clone();
+        let response = metrics::timed("trillian", "get_inclusion_proof", client.get_inclusion_proof(request))
             .await?
             .into_inner();
         match response.proof {
@@ -114,4 +179,160 @@ impl TrillianClient {
             None => Err(anyhow!("No inclusion proof available")),
         }
     }
+
+    /// Looks up the inclusion proof for a leaf value in a tree of the given
+    /// size. Returns `None` while the leaf has not been integrated yet.
+    pub async fn get_inclusion_proof_by_hash(&self, log_id: i64, leaf_value: &[u8], tree_size: i64) -> Result<Option<Proof>> {
+        let request = GetInclusionProofByHashRequest {
+            log_id,
+            leaf_hash: merkle::leaf_hash(leaf_value).to_vec(),
+            tree_size,
+            order_by_sequence: true,
+            charge_to: None,
+        };
+        let mut client = self.client.clone();
+        // Not found yet is the normal answer for a pending leaf, not an error
+        let lookup = async {
+            match client.get_inclusion_proof_by_hash(request).await {
+                Ok(response) => Ok(Some(response.into_inner())),
+                Err(status) if status.code() == tonic::Code::NotFound => Ok(None),
+                Err(status) => Err(status),
+            }
+        };
+        let response = metrics::timed("trillian", "get_inclusion_proof_by_hash", lookup).await?;
+        Ok(response.and_then(|response| response.proof.into_iter().next()))
+    }
+
+    pub async fn get_consistency_proof(&self, log_id: i64, first_tree_size: i64, second_tree_size: i64) -> Result<Vec<Vec<u8>>> {
+        let request = GetConsistencyProofRequest {
+            log_id,
+            first_tree_size,
+            second_tree_size,
+            charge_to: None,
+        };
+        let mut client = self.client.clone();
+        let response = metrics::timed("trillian", "get_consistency_proof", client.get_consistency_proof(request))
+            .await?
+            .into_inner();
+        match response.proof {
+            Some(proof) => Ok(proof.hashes),
+            None => Err(anyhow!("No consistency proof available")),
+        }
+    }
+
+    /// Fetches up to `count` leaves starting at `start_index`; fewer are
+    /// returned if the range runs past the end of the tree.
+    pub async fn get_leaves_by_range(&self, log_id: i64, start_index: i64, count: i64) -> Result<Vec<LogLeaf>> {
+        let request = GetLeavesByRangeRequest {
+            log_id,
+            start_index,
+            count,
+            charge_to: None,
+        };
+        let mut client = self.client.clone();
+        let response = metrics::timed("trillian", "get_leaves_by_range", client.get_leaves_by_range(request))
+            .await?
+            .into_inner();
+        Ok(response.leaves)
+    }
+}
+
+#[async_trait]
+impl TransparencyLog for TrillianClient {
+    async fn append(&self, log_id: i64, leaf_values: &[Vec<u8>]) -> Result<Vec<Result<QueuedLeaf>>> {
+        self.queue_leaves(log_id, leaf_values).await
+    }
+
+    async fn latest_root(&self, log_id: i64) -> Result<SignedRoot> {
+        self.get_current_root(log_id).await
+    }
+
+    async fn inclusion_proof(&self, log_id: i64, leaf_value: &[u8], tree_size: i64) -> Result<Option<InclusionProof>> {
+        Ok(self.get_inclusion_proof_by_hash(log_id, leaf_value, tree_size).await?
+            .map(|proof| InclusionProof {
+                leaf_index: proof.leaf_index,
+                hashes: proof.hashes,
+            }))
+    }
+
+    async fn consistency_proof(&self, log_id: i64, first_tree_size: i64, second_tree_size: i64) -> Result<Vec<Vec<u8>>> {
+        self.get_consistency_proof(log_id, first_tree_size, second_tree_size).await
+    }
+
+    async fn leaves_by_range(&self, log_id: i64, start_index: i64, count: i64) -> Result<Vec<Leaf>> {
+        Ok(self.get_leaves_by_range(log_id, start_index, count).await?
+            .into_iter()
+            .map(|leaf| Leaf {
+                leaf_index: leaf.leaf_index,
+                leaf_value: leaf.leaf_value,
+                merkle_leaf_hash: leaf.merkle_leaf_hash,
+            })
+            .collect())
+    }
+}
+
+/// Parses a TLS-serialized `LogRootV1`: version(2) + tree_size(8) +
+/// hash_len(1) + hash + timestamp_nanos(8) + ...
+fn parse_log_root(log_root: Vec<u8>) -> Result<SignedRoot> {
+    if log_root.len() < 11 {
+        return Err(anyhow!("Invalid log root: too short"));
+    }
+    let tree_size = i64::from_be_bytes(log_root[2..10].try_into()?);
+
+    let hash_len = log_root[10] as usize;
+    if log_root.len() < 11 + hash_len + 8 {
+        return Err(anyhow!("Invalid log root: hash too short"));
+    }
+    let root_hash = log_root[11..11 + hash_len].to_vec();
+    let timestamp_nanos = u64::from_be_bytes(log_root[11 + hash_len..19 + hash_len].try_into()?);
+
+    Ok(SignedRoot {
+        root_hash,
+        tree_size,
+        timestamp_nanos,
+        log_root,
+    })
+}
+
+/// Interprets a queued leaf. ALREADY_EXISTS is not an error: the log hands
+/// back the existing entry, which is reported as a duplicate. Any other
+/// non-OK status is a failure for that leaf.
+fn queued_leaf(ql: QueuedLogLeaf) -> Result<QueuedLeaf> {
+    let code = ql.status.as_ref().map(|status| status.code).unwrap_or(tonic::Code::Ok as i32);
+    if code != tonic::Code::Ok as i32 && code != tonic::Code::AlreadyExists as i32 {
+        // Kept as a Status so retries can tell transient codes apart
+        let message = ql.status.map(|status| status.message).unwrap_or_default();
+        return Err(tonic::Status::new(code.into(), format!("Leaf rejected by log: {}", message)).into());
+    }
+    match ql.leaf {
+        Some(leaf) => Ok(QueuedLeaf {
+            leaf_index: leaf.leaf_index,
+            duplicate: code == tonic::Code::AlreadyExists as i32,
+        }),
+        None => Err(anyhow!("Queued leaf missing leaf data")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sequencer_keeps_unintegrated_indices() {
+        let mut sequencer = Sequencer::default();
+        // Nothing gets integrated, so the log reports an empty tree throughout
+        let start = sequencer.start(async { Ok(0) }).await.unwrap();
+        assert_eq!(start, 0);
+        sequencer.advance(start + 3, true);
+        let start = sequencer.start(async { Ok(0) }).await.unwrap();
+        assert_eq!(start, 3);
+
+        // A failed write re-reads the tree size but keeps indices 0..3 taken
+        sequencer.advance(start, false);
+        assert_eq!(sequencer.start(async { Ok(0) }).await.unwrap(), 3);
+        // Another writer extended the log past the counter
+        assert_eq!(sequencer.start(async { Ok(8) }).await.unwrap(), 8);
+        // A log seen for the first time starts from its tree size
+        assert_eq!(Sequencer::default().start(async { Ok(5) }).await.unwrap(), 5);
+    }
 }
@@ -1,36 +1,103 @@
 use proxy_wasm as wasm;
 use wasm::traits::*;
 use wasm::types::*;
+use serde::Deserialize;
 use serde_json::{json, Value};
 use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::Duration;
 
 mod canonical;
 use canonical::canonicalise;
 
+// Filter configuration, from the plugin configuration JSON
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ProxyConfig {
+    // Envoy cluster of the auditor's REST gateway; hashes are only logged
+    // when unset. When the auditor requires client certificates, the
+    // cluster's upstream TLS context must present one naming `proxy_id`.
+    auditor_cluster: Option<String>,
+    // `:authority` for calls to the gateway
+    auditor_authority: String,
+    api_key: Option<String>,
+    proxy_id: String,
+}
+
 // Root context – created once per filter instance
-struct ProxyRoot;
+#[derive(Default)]
+struct ProxyRoot {
+    config: Rc<ProxyConfig>,
+}
 
 impl Context for ProxyRoot {}
 
 impl RootContext for ProxyRoot {
+    fn on_configure(&mut self, _plugin_configuration_size: usize) -> bool {
+        let Some(raw) = self.get_plugin_configuration() else {
+            return true;
+        };
+        match serde_json::from_slice(&raw) {
+            Ok(config) => {
+                self.config = Rc::new(config);
+                true
+            }
+            Err(e) => {
+                wasm::hostcalls::log(LogLevel::Error, &format!("Invalid filter configuration: {}", e)).unwrap();
+                false
+            }
+        }
+    }
+
     fn create_http_context(&self, _context_id: u32) -> Option<Box<dyn HttpContext>> {
-        Some(Box::new(ProxyHttp::default()))
+        Some(Box::new(ProxyHttp::new(self.config.clone())))
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        Some(ContextType::HttpContext)
     }
 }
 
+// W3C trace context headers, forwarded to the auditor so its spans join
+// the interaction's trace. Not part of the hashed context.
+const TRACE_HEADERS: [&str; 2] = ["traceparent", "tracestate"];
+
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// A hash waiting to be submitted, with the context it was computed from and
+// the trace context to send along as request headers
+struct QueuedHash {
+    hash: Vec<u8>,
+    context: Value,
+    trace_context: Vec<(String, String)>,
+}
+
 // Per‑stream HTTP context
-#[derive(Default)]
 struct ProxyHttp {
+    config: Rc<ProxyConfig>,
     request_headers: Vec<(String, String)>,
+    trace_context: Vec<(String, String)>,
     response_body: Option<Vec<u8>>,
-    hash_queue: VecDeque<Vec<u8>>,
+    hash_queue: VecDeque<QueuedHash>,
 }
 
-impl Context for ProxyHttp {}
+impl Context for ProxyHttp {
+    fn on_http_call_response(&mut self, _token_id: u32, _num_headers: usize, _body_size: usize, _num_trailers: usize) {
+        let status = self.get_http_call_response_header(":status").unwrap_or_default();
+        if !status.starts_with('2') {
+            wasm::hostcalls::log(LogLevel::Warn, &format!("Auditor rejected hash submission: status {}", status)).unwrap();
+        }
+    }
+}
 
 impl HttpContext for ProxyHttp {
     fn on_http_request_headers(&mut self, _num_headers: usize, _end_of_stream: bool) -> Action {
         let headers = self.get_http_request_headers();
+        self.trace_context = headers
+            .iter()
+            .filter(|(k, _)| TRACE_HEADERS.contains(&k.as_str()))
+            .cloned()
+            .collect();
         self.request_headers = headers
             .into_iter()
             .filter(|(k, _)| {
@@ -50,13 +117,24 @@ impl HttpContext for ProxyHttp {
                 let context = self.build_context();
                 let canonical = canonicalise(&context);
                 let hash = blake3::hash(&canonical).as_bytes().to_vec();
-                self.hash_queue.push_back(hash.clone());
+                self.hash_queue.push_back(QueuedHash {
+                    hash: hash.clone(),
+                    context,
+                    trace_context: self.trace_context.clone(),
+                });
 
+                let traceparent = self
+                    .trace_context
+                    .iter()
+                    .find(|(k, _)| k == "traceparent")
+                    .map(|(_, v)| v.as_str())
+                    .unwrap_or("-");
                 wasm::hostcalls::log(
                     wasm::types::LogLevel::Info,
-                    &format!("Computed hash: {}", hex::encode(&hash)),
+                    &format!("Computed hash: {} (traceparent {})", hex::encode(&hash), traceparent),
                 )
                 .unwrap();
+                self.submit_queued();
             }
         }
         Action::Continue
@@ -64,6 +142,50 @@ impl HttpContext for ProxyHttp {
 }
 
 impl ProxyHttp {
+    fn new(config: Rc<ProxyConfig>) -> Self {
+        Self {
+            config,
+            request_headers: Vec::new(),
+            trace_context: Vec::new(),
+            response_body: None,
+            hash_queue: VecDeque::new(),
+        }
+    }
+
+    // Submits queued hashes to the auditor's REST gateway. The trace headers
+    // go along so the auditor's spans join the interaction's trace.
+    fn submit_queued(&mut self) {
+        let Some(cluster) = self.config.auditor_cluster.clone() else {
+            return;
+        };
+        while let Some(queued) = self.hash_queue.pop_front() {
+            let body = json!({
+                "hash": hex::encode(&queued.hash),
+                "timestamp_ns": queued.context["timestamp_ns"],
+                "metadata": queued.context,
+                "proxy_id": self.config.proxy_id,
+            });
+            let body = serde_json::to_vec(&body).unwrap();
+            let mut headers = vec![
+                (":method", "POST"),
+                (":path", "/v1/receipts"),
+                (":authority", self.config.auditor_authority.as_str()),
+                ("content-type", "application/json"),
+            ];
+            if let Some(api_key) = &self.config.api_key {
+                headers.push(("x-api-key", api_key.as_str()));
+            }
+            headers.extend(queued.trace_context.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+            if let Err(status) = self.dispatch_http_call(&cluster, headers, Some(&body), vec![], SUBMIT_TIMEOUT) {
+                wasm::hostcalls::log(
+                    LogLevel::Error,
+                    &format!("Failed to submit hash {}: {:?}", hex::encode(&queued.hash), status),
+                )
+                .unwrap();
+            }
+        }
+    }
+
     fn build_context(&self) -> Value {
         let request_headers_obj: Value = self
             .request_headers
@@ -0,0 +1,6 @@
+//! Code shared by the VeriLLM services.
+
+pub mod cose;
+pub mod merkle;
+pub mod statements;
+pub mod telemetry;
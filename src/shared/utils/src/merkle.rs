@@ -0,0 +1,298 @@
+//! RFC 6962 Merkle tree hashing and proofs, computed over a tree's leaf
+//! hashes held in memory.
+
+use sha2::{Digest, Sha256};
+
+pub type Hash = [u8; 32];
+
+/// Merkle leaf hash: `SHA-256(0x00 || value)`.
+pub fn leaf_hash(value: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update(value);
+    hasher.finalize().into()
+}
+
+/// Interior node hash: `SHA-256(0x01 || left || right)`.
+pub fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Hashes of every complete subtree of an append-only tree, updated as
+/// leaves are pushed. Any range of leaves splits into O(log n) complete
+/// subtrees, so roots and proofs for any prefix of the tree are computed
+/// without rehashing every leaf.
+#[derive(Debug, Default)]
+pub struct Subtrees {
+    /// `levels[h][i]` is the root of leaves `i << h..(i + 1) << h`.
+    levels: Vec<Vec<Hash>>,
+}
+
+impl Subtrees {
+    pub fn push(&mut self, leaf: Hash) {
+        let mut hash = leaf;
+        for level in 0.. {
+            if self.levels.len() == level {
+                self.levels.push(Vec::new());
+            }
+            let nodes = &mut self.levels[level];
+            nodes.push(hash);
+            if nodes.len() % 2 == 1 {
+                break;
+            }
+            hash = node_hash(&nodes[nodes.len() - 2], &nodes[nodes.len() - 1]);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels.first().map_or(0, Vec::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn leaf(&self, index: usize) -> Hash {
+        self.levels[0][index]
+    }
+
+    /// Root of the tree of the first `size` leaves; the empty tree's root
+    /// is the hash of the empty string.
+    pub fn root(&self, size: usize) -> Hash {
+        match size {
+            0 => Sha256::digest(b"").into(),
+            size => self.range_root(0, size),
+        }
+    }
+
+    /// Audit path for the leaf at `index` in the tree of the first `size`
+    /// leaves, ordered from the leaf up (RFC 6962 section 2.1.1).
+    pub fn inclusion_path(&self, index: usize, size: usize) -> Vec<Hash> {
+        self.path(index, 0, size)
+    }
+
+    /// Proof that the tree of the first `first_size` leaves is a prefix of
+    /// the tree of the first `second_size` (RFC 6962 section 2.1.2).
+    pub fn consistency_proof(&self, first_size: usize, second_size: usize) -> Vec<Hash> {
+        if first_size == 0 || first_size >= second_size {
+            return Vec::new();
+        }
+        self.subproof(first_size, 0, second_size, true)
+    }
+
+    /// Root of the non-empty range of leaves `start..end`.
+    fn range_root(&self, start: usize, end: usize) -> Hash {
+        let n = end - start;
+        // Every subtree the RFC 6962 split produces starts at a multiple
+        // of its size once it is a power of two
+        if n.is_power_of_two() && start & (n - 1) == 0 {
+            return self.levels[n.trailing_zeros() as usize][start >> n.trailing_zeros()];
+        }
+        let mid = start + split(n);
+        node_hash(&self.range_root(start, mid), &self.range_root(mid, end))
+    }
+
+    fn path(&self, index: usize, start: usize, end: usize) -> Vec<Hash> {
+        if end - start <= 1 {
+            return Vec::new();
+        }
+        let mid = start + split(end - start);
+        let (mut path, sibling) = if index < mid {
+            (self.path(index, start, mid), self.range_root(mid, end))
+        } else {
+            (self.path(index, mid, end), self.range_root(start, mid))
+        };
+        path.push(sibling);
+        path
+    }
+
+    /// SUBPROOF of RFC 6962 for the old tree ending at leaf `m`, within
+    /// leaves `start..end`.
+    fn subproof(&self, m: usize, start: usize, end: usize, complete: bool) -> Vec<Hash> {
+        if m == end {
+            return if complete { Vec::new() } else { vec![self.range_root(start, end)] };
+        }
+        let mid = start + split(end - start);
+        let (mut proof, sibling) = if m <= mid {
+            (self.subproof(m, start, mid, complete), self.range_root(mid, end))
+        } else {
+            (self.subproof(m, mid, end, false), self.range_root(start, mid))
+        };
+        proof.push(sibling);
+        proof
+    }
+}
+
+/// Largest power of two smaller than `n` (for `n > 1`).
+fn split(n: usize) -> usize {
+    let mut k = 1;
+    while k << 1 < n {
+        k <<= 1;
+    }
+    k
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Leaves and roots from the certificate-transparency test vectors
+    const LEAVES: [&[u8]; 8] = [
+        b"",
+        b"\x00",
+        b"\x10",
+        b"\x20\x21",
+        b"\x30\x31",
+        b"\x40\x41\x42\x43",
+        b"\x50\x51\x52\x53\x54\x55\x56\x57",
+        b"\x60\x61\x62\x63\x64\x65\x66\x67\x68\x69\x6a\x6b\x6c\x6d\x6e\x6f",
+    ];
+
+    const ROOTS: [&str; 8] = [
+        "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+        "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+        "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+        "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+        "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+        "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+        "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+    ];
+
+    fn leaves(n: usize) -> Vec<Hash> {
+        (0..n).map(|i| leaf_hash(&[i as u8])).collect()
+    }
+
+    // RFC 9162 section 2.1.3.2
+    fn verify_inclusion(index: usize, size: usize, leaf: Hash, path: &[Hash], root: Hash) -> bool {
+        if index >= size {
+            return false;
+        }
+        let (mut f, mut s) = (index, size - 1);
+        let mut r = leaf;
+        for p in path {
+            if s == 0 {
+                return false;
+            }
+            if f & 1 == 1 || f == s {
+                r = node_hash(p, &r);
+                while f & 1 == 0 && f != 0 {
+                    f >>= 1;
+                    s >>= 1;
+                }
+            } else {
+                r = node_hash(&r, p);
+            }
+            f >>= 1;
+            s >>= 1;
+        }
+        s == 0 && r == root
+    }
+
+    // RFC 9162 section 2.1.4.2
+    fn verify_consistency(m: usize, n: usize, proof: &[Hash], first: Hash, second: Hash) -> bool {
+        if m == n {
+            return proof.is_empty() && first == second;
+        }
+        if m == 0 || proof.is_empty() {
+            return false;
+        }
+        let mut proof = proof.to_vec();
+        if m.is_power_of_two() {
+            proof.insert(0, first);
+        }
+        let (mut f, mut s) = (m - 1, n - 1);
+        while f & 1 == 1 {
+            f >>= 1;
+            s >>= 1;
+        }
+        let (mut fr, mut sr) = (proof[0], proof[0]);
+        for c in &proof[1..] {
+            if s == 0 {
+                return false;
+            }
+            if f & 1 == 1 || f == s {
+                fr = node_hash(c, &fr);
+                sr = node_hash(c, &sr);
+                while f & 1 == 0 && f != 0 {
+                    f >>= 1;
+                    s >>= 1;
+                }
+            } else {
+                sr = node_hash(&sr, c);
+            }
+            f >>= 1;
+            s >>= 1;
+        }
+        s == 0 && fr == first && sr == second
+    }
+
+    fn subtrees(leaves: &[Hash]) -> Subtrees {
+        let mut subtrees = Subtrees::default();
+        for leaf in leaves {
+            subtrees.push(*leaf);
+        }
+        subtrees
+    }
+
+    // RFC 6962 section 2.1, hashing every leaf
+    fn full_root(leaves: &[Hash]) -> Hash {
+        match leaves.len() {
+            0 => Sha256::digest(b"").into(),
+            1 => leaves[0],
+            n => node_hash(&full_root(&leaves[..split(n)]), &full_root(&leaves[split(n)..])),
+        }
+    }
+
+    #[test]
+    fn test_roots_match_reference_vectors() {
+        let hashes: Vec<Hash> = LEAVES.iter().map(|leaf| leaf_hash(leaf)).collect();
+        let tree = subtrees(&hashes);
+        for (n, expected) in ROOTS.iter().enumerate() {
+            assert_eq!(hex::encode(tree.root(n + 1)), *expected, "tree size {}", n + 1);
+        }
+    }
+
+    #[test]
+    fn test_roots_match_full_recomputation() {
+        let hashes = leaves(33);
+        let mut tree = Subtrees::default();
+        for n in 0..=33 {
+            assert_eq!(tree.len(), n);
+            assert_eq!(tree.root(n), full_root(&hashes[..n]), "tree size {}", n);
+            if n < 33 {
+                tree.push(hashes[n]);
+            }
+        }
+        for n in 0..=33 {
+            assert_eq!(tree.root(n), full_root(&hashes[..n]), "prefix of size {}", n);
+        }
+    }
+
+    #[test]
+    fn test_inclusion_paths_verify() {
+        let hashes = leaves(33);
+        let tree = subtrees(&hashes);
+        for n in 1..=33 {
+            for (index, leaf) in hashes.iter().enumerate().take(n) {
+                let path = tree.inclusion_path(index, n);
+                assert!(verify_inclusion(index, n, *leaf, &path, tree.root(n)), "leaf {} of {}", index, n);
+            }
+        }
+    }
+
+    #[test]
+    fn test_consistency_proofs_verify() {
+        let tree = subtrees(&leaves(33));
+        for n in 1..=33 {
+            for m in 1..=n {
+                let proof = tree.consistency_proof(m, n);
+                assert!(verify_consistency(m, n, &proof, tree.root(m), tree.root(n)), "{} -> {}", m, n);
+            }
+        }
+    }
+}
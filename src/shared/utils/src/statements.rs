@@ -0,0 +1,204 @@
+//! Statements the auditor signs and verifiers check, and the canonical
+//! encodings their signatures cover. Both sides build the signed bytes here,
+//! so they cannot drift apart.
+
+use anyhow::{anyhow, bail, Result};
+use base64::engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD as BASE64URL};
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// JWS `typ` of inclusion receipts.
+pub const RECEIPT_TYP: &str = "verillm-receipt+jwt";
+/// JWS `typ` of receipt promises, so one can never pass for the other.
+pub const PROMISE_TYP: &str = "verillm-promise+jwt";
+
+/// Domain-separation tags, signed inside each payload so a signature over
+/// one kind of statement cannot be presented as another, whatever the
+/// envelope around it.
+pub const RECEIPT_DOMAIN: &str = "verillm.receipt";
+pub const PROMISE_DOMAIN: &str = "verillm.promise";
+pub const REVOCATION_DOMAIN: &str = "verillm.revocation";
+/// Version of the signed payloads.
+pub const PAYLOAD_VERSION: u32 = 2;
+
+/// Ed25519 public key as an OKP JWK (RFC 8037).
+#[derive(Debug, Serialize)]
+pub struct Jwk {
+    kty: &'static str,
+    crv: &'static str,
+    x: String,
+    kid: String,
+    #[serde(rename = "use")]
+    key_use: &'static str,
+    alg: &'static str,
+}
+
+impl Jwk {
+    pub fn ed25519(kid: &str, key: &VerifyingKey) -> Self {
+        Jwk {
+            kty: "OKP",
+            crv: "Ed25519",
+            x: BASE64URL.encode(key.as_bytes()),
+            kid: kid.to_string(),
+            key_use: "sig",
+            alg: "EdDSA",
+        }
+    }
+}
+
+/// Protected header of the JWS statements. The public key travels alongside
+/// its `kid` so a receipt can be checked without fetching the JWKS.
+#[derive(Debug, Serialize)]
+struct JwsHeader<'a> {
+    alg: &'static str,
+    typ: &'a str,
+    kid: &'a str,
+    jwk: Jwk,
+}
+
+/// A signed statement withdrawing the receipt for `leaf_hash`, serialized as
+/// one JSON object. The signature covers the canonical encoding of every
+/// field but the signature and key themselves.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Revocation {
+    pub domain: String,
+    pub version: u32,
+    pub log_id: i64,
+    pub leaf_hash: String,
+    pub reason: String,
+    pub actor: String,
+    pub timestamp: String,
+    pub signature: String,
+    pub public_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+}
+
+impl Revocation {
+    pub fn sign(
+        key: &SigningKey,
+        kid: &str,
+        log_id: i64,
+        leaf_hash: &str,
+        reason: &str,
+        actor: &str,
+        timestamp: String,
+    ) -> Result<Self> {
+        let mut revocation = Revocation {
+            domain: REVOCATION_DOMAIN.to_string(),
+            version: PAYLOAD_VERSION,
+            log_id,
+            leaf_hash: leaf_hash.to_string(),
+            reason: reason.to_string(),
+            actor: actor.to_string(),
+            timestamp,
+            signature: String::new(),
+            public_key: BASE64.encode(key.verifying_key().as_bytes()),
+            kid: Some(kid.to_string()),
+        };
+        let signature = key.sign(&revocation.signing_input()?);
+        revocation.signature = BASE64.encode(signature.to_bytes());
+        Ok(revocation)
+    }
+
+    /// The bytes the signature covers.
+    pub fn signing_input(&self) -> Result<Vec<u8>> {
+        let payload = serde_json::json!({
+            "domain": self.domain,
+            "version": self.version,
+            "log_id": self.log_id,
+            "leaf_hash": self.leaf_hash,
+            "reason": self.reason,
+            "actor": self.actor,
+            "timestamp": self.timestamp,
+        });
+        Ok(canonical_json(&payload)?.into_bytes())
+    }
+
+    /// Checks the signature against the statement's own `public_key`; which
+    /// keys to trust is up to the caller.
+    pub fn verify_signature(&self) -> Result<()> {
+        if self.version != PAYLOAD_VERSION {
+            bail!("Unsupported revocation version {}", self.version);
+        }
+        if self.domain != REVOCATION_DOMAIN {
+            bail!("Not a revocation (domain {})", self.domain);
+        }
+        let public_key = BASE64.decode(&self.public_key)?;
+        let public_key = VerifyingKey::from_bytes(
+            public_key.as_slice().try_into().map_err(|_| anyhow!("Invalid public key length"))?,
+        )?;
+        let signature = Signature::from_slice(&BASE64.decode(&self.signature)?)?;
+        public_key.verify(&self.signing_input()?, &signature)
+            .map_err(|_| anyhow!("Invalid revocation signature"))
+    }
+}
+
+/// Compact JWS (RFC 7515) over the canonical encoding of `payload`, signed
+/// with EdDSA (RFC 8037).
+pub fn sign_jws<T: Serialize>(key: &SigningKey, kid: &str, typ: &str, payload: &T) -> Result<String> {
+    let header = JwsHeader {
+        alg: "EdDSA",
+        typ,
+        kid,
+        jwk: Jwk::ed25519(kid, &key.verifying_key()),
+    };
+    let signing_input = format!(
+        "{}.{}",
+        BASE64URL.encode(serde_json::to_vec(&header)?),
+        BASE64URL.encode(canonical_json(&serde_json::to_value(payload)?)?)
+    );
+    let signature = key.sign(signing_input.as_bytes());
+    Ok(format!("{}.{}", signing_input, BASE64URL.encode(signature.to_bytes())))
+}
+
+/// JSON with object members sorted by key and no insignificant whitespace,
+/// so the signed bytes follow from the content alone.
+pub fn canonical_json(value: &Value) -> Result<String> {
+    Ok(match value {
+        Value::Object(map) => {
+            let mut members: Vec<_> = map.iter().collect();
+            members.sort_by_key(|(key, _)| *key);
+            let members = members.into_iter()
+                .map(|(key, value)| Ok(format!("{}:{}", serde_json::to_string(key)?, canonical_json(value)?)))
+                .collect::<Result<Vec<_>>>()?;
+            format!("{{{}}}", members.join(","))
+        }
+        Value::Array(items) => {
+            let items = items.iter().map(canonical_json).collect::<Result<Vec<_>>>()?;
+            format!("[{}]", items.join(","))
+        }
+        scalar => serde_json::to_string(scalar)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_jws_verifies_over_header_and_payload() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let token = sign_jws(&key, "k1", RECEIPT_TYP, &serde_json::json!({"leaf_index": 7})).unwrap();
+        let parts: Vec<&str> = token.split('.').collect();
+        assert_eq!(parts.len(), 3);
+
+        let header: Value = serde_json::from_slice(&BASE64URL.decode(parts[0]).unwrap()).unwrap();
+        assert_eq!(header["alg"], "EdDSA");
+        assert_eq!(header["typ"], RECEIPT_TYP);
+        assert_eq!(header["kid"], "k1");
+        assert_eq!(BASE64URL.decode(parts[1]).unwrap(), br#"{"leaf_index":7}"#);
+
+        let signature = Signature::from_slice(&BASE64URL.decode(parts[2]).unwrap()).unwrap();
+        let signing_input = format!("{}.{}", parts[0], parts[1]);
+        assert!(key.verifying_key().verify(signing_input.as_bytes(), &signature).is_ok());
+    }
+
+    #[test]
+    fn test_canonical_json_sorts_nested_members() {
+        let value = serde_json::json!({"b": [{"z": 1, "a": null}], "a": {"y": "x", "c": true}});
+        assert_eq!(canonical_json(&value).unwrap(), r#"{"a":{"c":true,"y":"x"},"b":[{"a":null,"z":1}]}"#);
+    }
+}
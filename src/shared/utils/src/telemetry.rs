@@ -0,0 +1,124 @@
+//! Tracing setup for the auditor and the verifier. Spans always go to the
+//! log output; with an exporter they are also exported through
+//! OpenTelemetry, continuing traces started upstream (W3C `traceparent` in
+//! gRPC metadata or HTTP headers).
+
+use std::collections::HashMap;
+use anyhow::Result;
+use axum::{http::Request as HttpRequest, middleware::Next, response::Response};
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{global, Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{self as sdktrace, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::{info_span, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// Where spans are exported, in addition to the log output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Exporter {
+    None,
+    /// OTLP over gRPC.
+    Otlp { endpoint: String },
+    /// Spans as JSON on stdout, for local use.
+    Stdout,
+    /// Spans as JSON appended to a file.
+    File { path: String },
+}
+
+pub fn init(service_name: &str, exporter: &Exporter) -> Result<()> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let trace_config = sdktrace::config()
+        .with_resource(Resource::new([KeyValue::new("service.name", service_name.to_string())]));
+    let tracer = match exporter {
+        Exporter::None => None,
+        Exporter::Otlp { endpoint } => Some(
+            opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(endpoint),
+                )
+                .with_trace_config(trace_config)
+                .install_batch(runtime::Tokio)?,
+        ),
+        Exporter::Stdout | Exporter::File { .. } => {
+            let builder = opentelemetry_stdout::SpanExporter::builder();
+            let exporter = match exporter {
+                Exporter::File { path } => builder
+                    .with_writer(std::fs::OpenOptions::new().create(true).append(true).open(path)?)
+                    .build(),
+                _ => builder.build(),
+            };
+            let provider = TracerProvider::builder()
+                .with_simple_exporter(exporter)
+                .with_config(trace_config)
+                .build();
+            let tracer = provider.tracer(service_name.to_string());
+            global::set_tracer_provider(provider);
+            Some(tracer)
+        }
+    };
+
+    tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
+        .init();
+    Ok(())
+}
+
+/// Flushes spans still buffered for export.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+/// Trace context sent by the caller in gRPC metadata or HTTP headers.
+pub fn remote_context(headers: &axum::http::HeaderMap) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+/// Trace context carried by an individual submission, for streams that mix
+/// submissions from many interactions.
+pub fn submission_context(trace_context: &HashMap<String, String>) -> Option<Context> {
+    if trace_context.is_empty() {
+        return None;
+    }
+    Some(global::get_text_map_propagator(|propagator| propagator.extract(trace_context)))
+}
+
+/// Span for an incoming gRPC call, a child of the caller's trace if any.
+pub fn grpc_span(request: &axum::http::Request<()>) -> Span {
+    let span = info_span!("grpc", otel.name = %request.uri().path());
+    span.set_parent(remote_context(request.headers()));
+    span
+}
+
+/// Axum middleware doing for REST requests what [`grpc_span`] does for gRPC.
+pub async fn http_span<B>(request: HttpRequest<B>, next: Next<B>) -> Response {
+    let span = info_span!(
+        "http",
+        otel.name = %format!("{} {}", request.method(), request.uri().path()),
+    );
+    span.set_parent(remote_context(request.headers()));
+    next.run(request).instrument(span).await
+}
+
+struct HeaderExtractor<'a>(&'a axum::http::HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
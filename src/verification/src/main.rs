@@ -2,108 +2,386 @@ mod verify;
 
 use axum::{
     extract::State,
+    http::{HeaderMap, StatusCode},
     routing::post,
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use anyhow::Result;
-use tracing::info;
-use base64::engine::general_purpose::STANDARD as BASE64;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use tokio::sync::Mutex;
+use tracing::{info, Instrument};
+use verillm_shared_utils::telemetry::{self, Exporter};
+use base64::engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD as BASE64URL};
 use base64::Engine;
 
 // Receipt struct matching the one in signer
 #[derive(Debug, Deserialize)]
 struct Receipt {
+    log_id: i64,
     leaf_hash: String,
     leaf_index: i64,
+    tree_size: i64,
     root_hash: String,
     inclusion_proof: Vec<String>,
-    timestamp: String,
     metadata: serde_json::Value,
     signature: String,
     public_key: String,
+    #[serde(default)]
+    kid: Option<String>,
+    // Header and payload of a JWS receipt, which its signature covers
+    #[serde(skip)]
+    jws_signing_input: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 struct VerifyRequest {
-    receipt: String, // JSON string of Receipt (or base64 encoded)
+    receipt: String, // Compact JWS, or hex/base64 COSE_Sign1
+    /// Revocation statement for the receipt, as returned by GetReceipt. When
+    /// absent and an auditor is configured, the auditor is asked instead.
+    #[serde(default)]
+    revocation: Option<String>,
+    /// Hex hash the receipt is for. Required with a COSE receipt, which
+    /// proves inclusion of a leaf it does not itself contain.
+    #[serde(default)]
+    leaf_hash: Option<String>,
+    /// Base64 key a COSE receipt is checked with when no auditor is
+    /// configured to resolve its key ID.
+    #[serde(default)]
+    public_key: Option<String>,
+}
+
+/// What the key and revocation checks need from a receipt whose own proofs
+/// check out.
+struct Signed {
+    leaf_hash: String,
+    log_id: Option<i64>,
+    kid: Option<String>,
+    public_key: String,
 }
 
 #[derive(Debug, Serialize)]
 struct VerifyResponse {
     valid: bool,
     message: String,
+    revoked: bool,
+    /// Set when the auditor could not say whether the receipt was revoked,
+    /// so `valid` covers only the receipt itself.
+    revocation_unknown: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revocation: Option<RevocationStatus>,
+}
+
+#[derive(Debug, Serialize)]
+struct RevocationStatus {
+    reason: String,
+    actor: String,
+    revoked_at: String,
+}
+
+struct AppState {
+    /// REST gateway of the auditor that issued the receipts, from
+    /// `VERILLM_AUDITOR_URL`. When set, receipts must be signed by one of its
+    /// published keys and revocations are looked up there.
+    auditor_url: Option<String>,
+    http: reqwest::Client,
+    /// How long a fetched JWKS is used before it is fetched again, from
+    /// `VERILLM_JWKS_TTL_SECS`.
+    jwks_ttl: Duration,
+    /// The last JWKS fetched, with when. Held while fetching, so concurrent
+    /// requests wait for one fetch rather than each making their own.
+    jwks: Mutex<Option<(Instant, PublishedKeys)>>,
 }
 
-struct AppState {}
+impl VerifyResponse {
+    fn invalid(message: String) -> Self {
+        Self {
+            valid: false,
+            message,
+            revoked: false,
+            revocation_unknown: false,
+            revocation: None,
+        }
+    }
+}
+
+/// The auditor's answer to whether a receipt was revoked.
+enum RevocationLookup {
+    /// Nothing to check: no auditor is configured, or it has no revocation
+    /// for the receipt.
+    None,
+    Statement(String),
+    /// The auditor could not be asked or would not answer, with why.
+    Unknown(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Jwk {
+    kid: String,
+    x: String,
+}
+
+/// The auditor's signing keys, current and retired, as (kid, public key)
+/// pairs with the key base64 encoded the way receipts carry it.
+type PublishedKeys = Vec<(String, String)>;
+
+/// The auditor's `PublishedKeys`, or `None` without an auditor. Cached for
+/// `jwks_ttl`; keys are published before they start signing, so a cached set
+/// only misses a key that was added without notice.
+async fn published_keys(state: &AppState) -> Result<Option<PublishedKeys>> {
+    let Some(auditor_url) = &state.auditor_url else {
+        return Ok(None);
+    };
+    let mut cached = state.jwks.lock().await;
+    if let Some((fetched_at, keys)) = cached.as_ref() {
+        if fetched_at.elapsed() < state.jwks_ttl {
+            return Ok(Some(keys.clone()));
+        }
+    }
+    let jwks: Jwks = state.http.get(format!("{}/.well-known/jwks.json", auditor_url.trim_end_matches('/')))
+        .send().await?
+        .error_for_status()?
+        .json().await?;
+    let keys = jwks.keys.into_iter()
+        .map(|jwk| Ok((jwk.kid, BASE64.encode(BASE64URL.decode(&jwk.x)?))))
+        .collect::<Result<Vec<_>>>()?;
+    *cached = Some((Instant::now(), keys.clone()));
+    Ok(Some(keys))
+}
+
+/// Fetches the receipt's revocation statement from the auditor, if it has
+/// one, with the caller's API key. An auditor that does not know the receipt
+/// has not revoked it; one that refuses the caller or fails leaves the
+/// status unknown.
+async fn lookup_revocation(state: &AppState, headers: &HeaderMap, leaf_hash: &str) -> RevocationLookup {
+    let Some(auditor_url) = &state.auditor_url else {
+        return RevocationLookup::None;
+    };
+    let mut request = state.http.get(format!("{}/v1/receipts/{}", auditor_url.trim_end_matches('/'), leaf_hash));
+    for name in ["authorization", "x-api-key"] {
+        if let Some(value) = headers.get(name) {
+            request = request.header(name, value);
+        }
+    }
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(e) => return RevocationLookup::Unknown(format!("auditor unreachable: {}", e)),
+    };
+    match response.status() {
+        StatusCode::NOT_FOUND => return RevocationLookup::None,
+        status if !status.is_success() => return RevocationLookup::Unknown(format!("auditor returned {}", status)),
+        _ => {}
+    }
+    let receipt: serde_json::Value = match response.json().await {
+        Ok(receipt) => receipt,
+        Err(e) => return RevocationLookup::Unknown(format!("unreadable auditor response: {}", e)),
+    };
+    match receipt.get("revocation")
+        .and_then(|revocation| revocation.get("statement"))
+        .and_then(|statement| statement.as_str())
+    {
+        Some(statement) => RevocationLookup::Statement(statement.to_string()),
+        None => RevocationLookup::None,
+    }
+}
+
+/// Checks a JWS receipt, which carries its own key.
+async fn verify_jws(receipt: &str) -> Result<Signed, VerifyResponse> {
+    if !verify::is_compact_jws(receipt) {
+        return Err(VerifyResponse::invalid("Receipt is neither a compact JWS nor a COSE_Sign1".to_string()));
+    }
+    let parsed = match verify::parse_jws(receipt) {
+        Ok(r) => r,
+        Err(e) => return Err(VerifyResponse::invalid(format!("Invalid JWS receipt: {}", e))),
+    };
+
+    // Perform verification
+    let span = tracing::info_span!(
+        "verify_receipt",
+        leaf_hash = %parsed.leaf_hash,
+        leaf_index = parsed.leaf_index,
+        log_id = parsed.log_id,
+    );
+    match verify::verify_receipt(&parsed).instrument(span).await {
+        Ok(true) => {}
+        Ok(false) => return Err(VerifyResponse::invalid("Receipt verification failed".to_string())),
+        Err(e) => return Err(VerifyResponse::invalid(format!("Verification error: {}", e))),
+    }
+    Ok(Signed {
+        leaf_hash: parsed.leaf_hash,
+        log_id: Some(parsed.log_id),
+        kid: parsed.kid,
+        public_key: parsed.public_key,
+    })
+}
+
+/// The published key matching a receipt's key ID, its public key, or both.
+fn published_key<'a>(published: &'a [(String, String)], kid: Option<&str>, public_key: Option<&str>) -> Option<&'a str> {
+    published.iter()
+        .find(|(published_kid, published_key)| {
+            (kid.is_none() || kid == Some(published_kid.as_str()))
+                && (public_key.is_none() || public_key == Some(published_key.as_str()))
+        })
+        .map(|(_, public_key)| public_key.as_str())
+}
+
+/// Checks a COSE receipt, which names its key only by ID: the key comes from
+/// the auditor's JWKS, or from the request when there is no auditor.
+fn verify_cose(req: &VerifyRequest, cose: &[u8], published: Option<&[(String, String)]>) -> Result<Signed, VerifyResponse> {
+    let invalid = |e: anyhow::Error| VerifyResponse::invalid(format!("Invalid COSE receipt: {}", e));
+    let leaf_hash = req.leaf_hash.clone()
+        .ok_or_else(|| VerifyResponse::invalid("leaf_hash is required with a COSE receipt".to_string()))?;
+    let kid = verify::cose_kid(cose).map_err(invalid)?;
+    let public_key = match published {
+        Some(published) => published_key(published, Some(&kid), None)
+            .map(str::to_string)
+            .ok_or_else(|| VerifyResponse::invalid("Receipt was not signed by a published auditor key".to_string()))?,
+        None => req.public_key.clone()
+            .ok_or_else(|| VerifyResponse::invalid("public_key is required with a COSE receipt when no auditor is configured".to_string()))?,
+    };
+    let leaf = hex::decode(&leaf_hash).map_err(|e| invalid(e.into()))?;
+    let key = BASE64.decode(&public_key).map_err(|e| invalid(e.into()))?;
+
+    let span = tracing::info_span!("verify_cose_receipt", leaf_hash = %leaf_hash, kid = %kid);
+    let _entered = span.enter();
+    match verify::verify_cose_receipt(cose, &leaf, &key) {
+        Ok(true) => {}
+        Ok(false) => return Err(VerifyResponse::invalid("Receipt verification failed".to_string())),
+        Err(e) => return Err(invalid(e)),
+    }
+    Ok(Signed {
+        leaf_hash,
+        log_id: None,
+        kid: Some(kid),
+        public_key,
+    })
+}
 
 async fn verify_handler(
-    State(_state): State<Arc<AppState>>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     Json(req): Json<VerifyRequest>,
 ) -> Json<VerifyResponse> {
-    // Try to parse as JSON directly (if not base64 encoded)
-    let receipt: Receipt = match serde_json::from_str(&req.receipt) {
-        Ok(r) => r,
-        Err(_) => {
-            // If that fails, try base64 decode then JSON parse
-            match BASE64.decode(&req.receipt) {
-                Ok(bytes) => {
-                    match serde_json::from_slice(&bytes) {
-                        Ok(r) => r,
-                        Err(e) => {
-                            return Json(VerifyResponse {
-                                valid: false,
-                                message: format!("Invalid receipt JSON after base64: {}", e),
-                            });
-                        }
-                    }
-                }
-                Err(e) => {
-                    return Json(VerifyResponse {
-                        valid: false,
-                        message: format!("Invalid base64: {}", e),
-                    });
-                }
-            }
-        }
+    let published = match published_keys(&state).await {
+        Ok(published) => published,
+        Err(e) => return Json(VerifyResponse::invalid(format!("Key lookup failed: {}", e))),
+    };
+    let receipt = match verify::decode_cose(&req.receipt) {
+        Some(cose) => verify_cose(&req, &cose, published.as_deref()),
+        None => verify_jws(&req.receipt).await,
     };
+    match receipt {
+        Ok(receipt) => Json(check_signer_and_revocation(&state, &headers, req.revocation, receipt, published).await),
+        Err(response) => Json(response),
+    }
+}
 
-    // Perform verification
-    match verify::verify_receipt(&receipt).await {
-        Ok(valid) => {
-            if valid {
-                Json(VerifyResponse {
-                    valid: true,
-                    message: "Receipt is valid".to_string(),
-                })
-            } else {
-                Json(VerifyResponse {
-                    valid: false,
-                    message: "Receipt verification failed".to_string(),
-                })
-            }
-        }
-        Err(e) => {
-            Json(VerifyResponse {
-                valid: false,
-                message: format!("Verification error: {}", e),
-            })
+/// What every receipt whose own proofs hold is checked for, whatever its
+/// format: that one of the auditor's keys signed it, and that it has not
+/// been revoked since, by `revocation` or by what the auditor says.
+async fn check_signer_and_revocation(
+    state: &AppState,
+    headers: &HeaderMap,
+    revocation: Option<String>,
+    receipt: Signed,
+    published: Option<PublishedKeys>,
+) -> VerifyResponse {
+    if let Some(published) = &published {
+        if published_key(published, receipt.kid.as_deref(), Some(&receipt.public_key)).is_none() {
+            return VerifyResponse::invalid("Receipt was not signed by a published auditor key".to_string());
         }
     }
+    let published_keys: Vec<String> = published.into_iter().flatten().map(|(_, public_key)| public_key).collect();
+
+    let valid = "Receipt is valid";
+
+    // An authentic receipt that has been withdrawn is no longer valid
+    let lookup = match revocation {
+        Some(statement) => RevocationLookup::Statement(statement),
+        None => lookup_revocation(state, headers, &receipt.leaf_hash).await,
+    };
+    let statement = match lookup {
+        RevocationLookup::None => None,
+        RevocationLookup::Statement(statement) => Some(statement),
+        RevocationLookup::Unknown(why) => return VerifyResponse {
+            valid: true,
+            message: format!("{}; its revocation status is unknown: {}", valid, why),
+            revoked: false,
+            revocation_unknown: true,
+            revocation: None,
+        },
+    };
+    match statement.map(|statement| verify::verify_revocation(&receipt, &statement, &published_keys)) {
+        None => VerifyResponse {
+            valid: true,
+            message: valid.to_string(),
+            revoked: false,
+            revocation_unknown: false,
+            revocation: None,
+        },
+        Some(Ok(revocation)) => VerifyResponse {
+            valid: false,
+            message: format!("Receipt was revoked: {}", revocation.reason),
+            revoked: true,
+            revocation_unknown: false,
+            revocation: Some(RevocationStatus {
+                reason: revocation.reason,
+                actor: revocation.actor,
+                revoked_at: revocation.timestamp,
+            }),
+        },
+        Some(Err(e)) => VerifyResponse::invalid(format!("Invalid revocation: {}", e)),
+    }
+}
+
+/// Span exporter selected by `OTEL_TRACES_EXPORTER`: `otlp` (to
+/// `OTEL_EXPORTER_OTLP_ENDPOINT`), `stdout`, `file` (appending to
+/// `VERILLM_TRACES_FILE`) or `none`, the default.
+fn span_exporter() -> Result<Exporter> {
+    let exporter = std::env::var("OTEL_TRACES_EXPORTER").unwrap_or_else(|_| "none".to_string());
+    Ok(match exporter.as_str() {
+        "none" => Exporter::None,
+        "otlp" => Exporter::Otlp {
+            endpoint: std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+                .unwrap_or_else(|_| "http://localhost:4317".to_string()),
+        },
+        "stdout" => Exporter::Stdout,
+        "file" => Exporter::File {
+            path: std::env::var("VERILLM_TRACES_FILE")
+                .map_err(|_| anyhow!("VERILLM_TRACES_FILE is required for the file exporter"))?,
+        },
+        other => return Err(anyhow!("Unknown OTEL_TRACES_EXPORTER: {}", other)),
+    })
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
-    let state = Arc::new(AppState {});
+    telemetry::init("verillm-verification", &span_exporter()?)?;
+    let jwks_ttl = match std::env::var("VERILLM_JWKS_TTL_SECS") {
+        Ok(secs) => secs.parse().map_err(|e| anyhow!("Invalid VERILLM_JWKS_TTL_SECS: {}", e))?,
+        Err(_) => 300,
+    };
+    let state = Arc::new(AppState {
+        auditor_url: std::env::var("VERILLM_AUDITOR_URL").ok(),
+        http: reqwest::Client::new(),
+        jwks_ttl: Duration::from_secs(jwks_ttl),
+        jwks: Mutex::new(None),
+    });
     let app = Router::new()
         .route("/verify", post(verify_handler))
+        .layer(axum::middleware::from_fn(telemetry::http_span))
         .with_state(state);
     let addr = "0.0.0.0:3001".parse()?;
     info!("Verification API listening on {}", addr);
-    axum::Server::bind(&addr)
+    let result = axum::Server::bind(&addr)
         .serve(app.into_make_service())
-        .await?;
-    Ok(())
+        .await;
+    telemetry::shutdown();
+    Ok(result?)
 }
@@ -1,21 +1,27 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
 use serde_json::Value;
 use blake3::Hash;
+use coset::cbor::value::Value as CborValue;
+use coset::{iana, CborSerializable, CoseSign1, Label, RegisteredLabelWithPrivate, TaggedCborSerializable};
 use ed25519_dalek::{VerifyingKey, Signature, Verifier};
-use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD as BASE64URL};
 use base64::Engine;
 use hex;
+use verillm_shared_utils::cose::{rfc9162_root, COSE_VDP, COSE_VDS, VDP_INCLUSION_PROOFS, VDS_RFC9162_SHA256};
+use verillm_shared_utils::statements::{Revocation, PAYLOAD_VERSION, RECEIPT_DOMAIN, RECEIPT_TYP};
 
 #[derive(Debug)]
 struct ParsedReceipt {
     leaf_hash: Vec<u8>,
     leaf_index: i64,
+    tree_size: i64,
     root_hash: Vec<u8>,
     inclusion_proof: Vec<Vec<u8>>,
-    timestamp: String,
     metadata: Value,
     signature: Vec<u8>,
     public_key: Vec<u8>,
+    jws_signing_input: String,
 }
 
 impl ParsedReceipt {
@@ -23,18 +29,84 @@ impl ParsedReceipt {
         Ok(ParsedReceipt {
             leaf_hash: hex::decode(&receipt.leaf_hash)?,
             leaf_index: receipt.leaf_index,
+            tree_size: receipt.tree_size,
             root_hash: hex::decode(&receipt.root_hash)?,
             inclusion_proof: receipt.inclusion_proof.iter()
                 .map(|h| hex::decode(h))
                 .collect::<Result<Vec<Vec<u8>>, hex::FromHexError>>()?,
-            timestamp: receipt.timestamp.clone(),
             metadata: receipt.metadata.clone(),
             signature: BASE64.decode(&receipt.signature)?,
             public_key: BASE64.decode(&receipt.public_key)?,
+            jws_signing_input: receipt.jws_signing_input.clone()
+                .ok_or_else(|| anyhow!("Receipt is not a JWS"))?,
         })
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct JwsHeader {
+    alg: String,
+    #[serde(default)]
+    typ: Option<String>,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default)]
+    jwk: Option<HeaderJwk>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HeaderJwk {
+    kty: String,
+    crv: String,
+    x: String,
+}
+
+/// Whether `receipt` is a compact JWS rather than JSON, plain or base64
+/// encoded; neither of those can contain dots outside a JSON object.
+pub fn is_compact_jws(receipt: &str) -> bool {
+    let receipt = receipt.trim();
+    !receipt.starts_with('{') && receipt.split('.').count() == 3
+}
+
+/// Unpacks a compact JWS receipt. The signature is checked by
+/// `verify_receipt`, along with everything else.
+pub fn parse_jws(token: &str) -> Result<super::Receipt> {
+    let token = token.trim();
+    let Some((signing_input, signature)) = token.rsplit_once('.') else {
+        bail!("Malformed JWS");
+    };
+    let Some((header, payload)) = signing_input.split_once('.') else {
+        bail!("Malformed JWS");
+    };
+
+    let header: JwsHeader = serde_json::from_slice(&BASE64URL.decode(header)?)?;
+    if header.alg != "EdDSA" {
+        bail!("Unsupported JWS algorithm {}", header.alg);
+    }
+    if header.typ.as_deref() != Some(RECEIPT_TYP) {
+        bail!("Not an inclusion receipt (typ {:?})", header.typ.unwrap_or_default());
+    }
+    let jwk = header.jwk.ok_or_else(|| anyhow!("JWS header has no jwk"))?;
+    if jwk.kty != "OKP" || jwk.crv != "Ed25519" {
+        bail!("Unsupported JWS key type {} {}", jwk.kty, jwk.crv);
+    }
+
+    // Fill in what the header carries
+    let mut receipt: serde_json::Map<String, Value> = serde_json::from_slice(&BASE64URL.decode(payload)?)?;
+    if receipt.get("domain").and_then(Value::as_str) != Some(RECEIPT_DOMAIN) {
+        bail!("Not an inclusion receipt (domain {})", receipt.get("domain").unwrap_or(&Value::Null));
+    }
+    if receipt.get("version").and_then(Value::as_u64) != Some(PAYLOAD_VERSION.into()) {
+        bail!("Unsupported receipt version {}", receipt.get("version").unwrap_or(&Value::Null));
+    }
+    receipt.insert("signature".to_string(), Value::from(BASE64.encode(BASE64URL.decode(signature)?)));
+    receipt.insert("public_key".to_string(), Value::from(BASE64.encode(BASE64URL.decode(&jwk.x)?)));
+    receipt.insert("kid".to_string(), Value::from(header.kid));
+    let mut receipt: super::Receipt = serde_json::from_value(Value::Object(receipt))?;
+    receipt.jws_signing_input = Some(signing_input.to_string());
+    Ok(receipt)
+}
+
 pub async fn verify_receipt(receipt_json: &super::Receipt) -> Result<bool> {
     let receipt = ParsedReceipt::from_json(receipt_json)?;
 
@@ -45,23 +117,13 @@ pub async fn verify_receipt(receipt_json: &super::Receipt) -> Result<bool> {
     }
 
     // 2. Merkle inclusion proof verification
-    if !verify_inclusion_proof(
-        &receipt.leaf_hash,
-        &receipt.root_hash,
-        &receipt.inclusion_proof,
-    )? {
+    let root = rfc9162_root(receipt.leaf_index, receipt.tree_size, &receipt.leaf_hash, &receipt.inclusion_proof);
+    if root.as_deref() != Some(receipt.root_hash.as_slice()) {
         return Ok(false);
     }
 
     // 3. Signature verification
-    if !verify_signature(
-        &receipt.leaf_hash,
-        receipt.leaf_index,
-        &receipt.root_hash,
-        &receipt.timestamp,
-        &receipt.signature,
-        &receipt.public_key,
-    )? {
+    if !verify_signature(&receipt)? {
         return Ok(false);
     }
 
@@ -73,40 +135,284 @@ fn recompute_leaf_hash(context: &Value) -> Result<Hash> {
     Ok(blake3::hash(&canonical))
 }
 
-fn verify_inclusion_proof(
-    leaf_hash: &[u8],
-    root_hash: &[u8],
-    proof: &[Vec<u8>],
-) -> Result<bool> {
-    let mut current = leaf_hash.to_vec();
-    for sibling in proof {
-        let combined = [current.as_slice(), sibling.as_slice()].concat();
-        current = blake3::hash(&combined).as_bytes().to_vec();
-    }
-    Ok(current == root_hash)
+/// Receipts are JWS over the canonical encoding of the whole receipt,
+/// domain tag and version included.
+fn verify_signature(receipt: &ParsedReceipt) -> Result<bool> {
+    verify_ed25519(receipt.jws_signing_input.as_bytes(), &receipt.signature, &receipt.public_key)
 }
 
-fn verify_signature(
-    leaf_hash: &[u8],
-    leaf_index: i64,
-    root_hash: &[u8],
-    timestamp: &str,
-    signature: &[u8],
-    public_key: &[u8],
-) -> Result<bool> {
-    // Reconstruct the canonical string that was signed
-    let canonical_string = format!(
-        "{}:{}:{}:{}",
-        hex::encode(leaf_hash),
-        leaf_index,
-        hex::encode(root_hash),
-        timestamp
-    );
-
+fn verify_ed25519(message: &[u8], signature: &[u8], public_key: &[u8]) -> Result<bool> {
     // Convert public key and signature
     let verifying_key = VerifyingKey::from_bytes(public_key.try_into().map_err(|_| anyhow!("Invalid public key length"))?)?;
     let sig = Signature::from_bytes(signature.try_into().map_err(|_| anyhow!("Invalid signature length"))?);
 
     // Verify
-    Ok(verifying_key.verify(canonical_string.as_bytes(), &sig).is_ok())
+    Ok(verifying_key.verify(message, &sig).is_ok())
+}
+
+/// Checks that a revocation statement withdraws this receipt and was signed
+/// by the key that signed the receipt or, after a key rotation, by another of
+/// the auditor's `published_keys`.
+pub fn verify_revocation(receipt: &super::Signed, statement: &str, published_keys: &[String]) -> Result<Revocation> {
+    let revocation: Revocation = serde_json::from_str(statement)?;
+    if !revocation.leaf_hash.eq_ignore_ascii_case(&receipt.leaf_hash) {
+        bail!("Revocation is for a different leaf");
+    }
+    if receipt.log_id.map_or(false, |log_id| log_id != revocation.log_id) {
+        bail!("Revocation is for a different log");
+    }
+    if revocation.public_key != receipt.public_key && !published_keys.contains(&revocation.public_key) {
+        bail!("Revocation was not signed by an auditor key");
+    }
+    revocation.verify_signature()?;
+    Ok(revocation)
+}
+
+/// A COSE receipt, hex or base64 encoded: a COSE_Sign1, tagged or not.
+pub fn decode_cose(receipt: &str) -> Option<Vec<u8>> {
+    let receipt = receipt.trim();
+    let bytes = hex::decode(receipt).ok().or_else(|| BASE64.decode(receipt).ok())?;
+    // Tag 18 or a bare four-element array; JSON starts with '{'
+    matches!(bytes.first().copied(), Some(0xd2 | 0x84)).then_some(bytes)
+}
+
+fn parse_cose(cose: &[u8]) -> Result<CoseSign1> {
+    CoseSign1::from_tagged_slice(cose)
+        .or_else(|_| CoseSign1::from_slice(cose))
+        .map_err(|e| anyhow!("Invalid COSE_Sign1: {:?}", e))
+}
+
+/// Key ID of the key that signed a COSE receipt.
+pub fn cose_kid(cose: &[u8]) -> Result<String> {
+    Ok(String::from_utf8(parse_cose(cose)?.protected.header.key_id)?)
+}
+
+/// Checks a COSE receipt for the submitted `leaf_hash`: the tree root is
+/// recomputed from the inclusion proof in the unprotected header, then the
+/// signature is verified with that root as the detached payload.
+pub fn verify_cose_receipt(cose: &[u8], leaf_hash: &[u8], public_key: &[u8]) -> Result<bool> {
+    let sign1 = parse_cose(cose)?;
+    let protected = &sign1.protected.header;
+    if protected.alg != Some(RegisteredLabelWithPrivate::Assigned(iana::Algorithm::EdDSA)) {
+        bail!("Unsupported COSE algorithm {:?}", protected.alg);
+    }
+    if header_value(&protected.rest, COSE_VDS).and_then(cbor_i64) != Some(VDS_RFC9162_SHA256) {
+        bail!("Not a receipt for an RFC 9162 SHA-256 log");
+    }
+
+    let proof = header_value(&sign1.unprotected.rest, COSE_VDP)
+        .and_then(CborValue::as_map)
+        .and_then(|proofs| proofs.iter().find(|(label, _)| cbor_i64(label) == Some(VDP_INCLUSION_PROOFS)))
+        .and_then(|(_, inclusion_proofs)| inclusion_proofs.as_array())
+        .and_then(|inclusion_proofs| inclusion_proofs.first())
+        .and_then(CborValue::as_bytes)
+        .ok_or_else(|| anyhow!("Receipt has no inclusion proof"))?;
+    let proof: CborValue = coset::cbor::de::from_reader(proof.as_slice())
+        .map_err(|e| anyhow!("Malformed inclusion proof: {:?}", e))?;
+    let (tree_size, leaf_index, path) = match proof.as_array().map(Vec::as_slice) {
+        Some([tree_size, leaf_index, CborValue::Array(path)]) => (cbor_i64(tree_size), cbor_i64(leaf_index), path),
+        _ => bail!("Malformed inclusion proof"),
+    };
+    let (Some(tree_size), Some(leaf_index)) = (tree_size, leaf_index) else {
+        bail!("Malformed inclusion proof");
+    };
+    let path = path.iter()
+        .map(|hash| hash.as_bytes().cloned().ok_or_else(|| anyhow!("Malformed inclusion proof")))
+        .collect::<Result<Vec<_>>>()?;
+    let Some(root) = rfc9162_root(leaf_index, tree_size, leaf_hash, &path) else {
+        return Ok(false);
+    };
+
+    let mut signed = false;
+    sign1.verify_detached_signature(&root, &[], |signature, data| -> Result<()> {
+        signed = verify_ed25519(data, signature, public_key)?;
+        Ok(())
+    })?;
+    Ok(signed)
+}
+
+fn header_value(rest: &[(Label, CborValue)], label: i64) -> Option<&CborValue> {
+    rest.iter().find(|(key, _)| *key == Label::Int(label)).map(|(_, value)| value)
+}
+
+fn cbor_i64(value: &CborValue) -> Option<i64> {
+    value.as_integer().and_then(|integer| i64::try_from(integer).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+    use verillm_shared_utils::cose;
+    use verillm_shared_utils::merkle::{self, Subtrees};
+    use verillm_shared_utils::statements::{sign_jws, PROMISE_DOMAIN, PROMISE_TYP};
+
+    /// A tree of `size` leaves holding `leaf` at `index`, as the auditor's
+    /// log builds it.
+    fn tree(size: u8, index: u8, leaf: &[u8]) -> Subtrees {
+        let mut tree = Subtrees::default();
+        for i in 0..size {
+            let other = [i; 32];
+            tree.push(merkle::leaf_hash(if i == index { leaf } else { &other }));
+        }
+        tree
+    }
+
+    /// A receipt payload as `Signer::sign_receipt` builds it, for the third
+    /// leaf of five.
+    fn receipt_payload() -> serde_json::Map<String, Value> {
+        let metadata = serde_json::json!({"model": "m", "prompt": "p"});
+        let leaf_hash = blake3::hash(&serde_json::to_vec(&metadata).unwrap());
+        let tree = tree(5, 2, leaf_hash.as_bytes());
+        let inclusion_proof: Vec<String> = tree.inclusion_path(2, 5).iter().map(hex::encode).collect();
+        let payload = serde_json::json!({
+            "domain": RECEIPT_DOMAIN,
+            "version": PAYLOAD_VERSION,
+            "log_id": 3,
+            "leaf_hash": leaf_hash.to_hex().to_string(),
+            "leaf_index": 2,
+            "tree_size": 5,
+            "root_hash": hex::encode(tree.root(5)),
+            "inclusion_proof": inclusion_proof,
+            "timestamp": "2025-05-01T00:00:00+00:00",
+            "metadata": metadata,
+        });
+        payload.as_object().unwrap().clone()
+    }
+
+    fn token(typ: &str, payload: &serde_json::Map<String, Value>) -> String {
+        sign_jws(&SigningKey::from_bytes(&[7; 32]), "k1", typ, payload).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_jws_receipt_verifies() {
+        let receipt = parse_jws(&token(RECEIPT_TYP, &receipt_payload())).unwrap();
+        assert_eq!(receipt.kid.as_deref(), Some("k1"));
+        assert!(verify_receipt(&receipt).await.unwrap());
+    }
+
+    #[test]
+    fn test_jws_of_another_version_is_rejected() {
+        let mut payload = receipt_payload();
+        payload.insert("version".to_string(), Value::from(PAYLOAD_VERSION + 1));
+        assert!(parse_jws(&token(RECEIPT_TYP, &payload)).is_err());
+        // Nor is a receipt without the tree size its proof is checked against
+        let mut payload = receipt_payload();
+        payload.remove("tree_size");
+        assert!(parse_jws(&token(RECEIPT_TYP, &payload)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_modified_jws_payload_does_not_verify() {
+        let token = token(RECEIPT_TYP, &receipt_payload());
+        let parts: Vec<&str> = token.split('.').collect();
+        let mut payload: serde_json::Map<String, Value> =
+            serde_json::from_slice(&BASE64URL.decode(parts[1]).unwrap()).unwrap();
+        payload.insert("log_id".to_string(), Value::from(4));
+        let payload = BASE64URL.encode(serde_json::to_vec(&payload).unwrap());
+        let receipt = parse_jws(&format!("{}.{}.{}", parts[0], payload, parts[2])).unwrap();
+        assert!(!verify_receipt(&receipt).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_receipt_with_a_tampered_sibling_does_not_verify() {
+        let mut payload = receipt_payload();
+        let mut proof = payload["inclusion_proof"].as_array().unwrap().clone();
+        proof[1] = Value::from("00".repeat(32));
+        payload.insert("inclusion_proof".to_string(), Value::from(proof));
+        let receipt = parse_jws(&token(RECEIPT_TYP, &payload)).unwrap();
+        assert!(!verify_receipt(&receipt).await.unwrap());
+
+        // Nor does the proof hold for another position in the tree
+        let mut payload = receipt_payload();
+        payload.insert("leaf_index".to_string(), Value::from(3));
+        let receipt = parse_jws(&token(RECEIPT_TYP, &payload)).unwrap();
+        assert!(!verify_receipt(&receipt).await.unwrap());
+    }
+
+    #[test]
+    fn test_promise_is_not_a_receipt() {
+        let mut promise = receipt_payload();
+        promise.insert("domain".to_string(), Value::from(PROMISE_DOMAIN));
+        assert!(parse_jws(&token(PROMISE_TYP, &promise)).is_err());
+        // Nor under a receipt's typ, since the domain tag is signed
+        assert!(parse_jws(&token(RECEIPT_TYP, &promise)).is_err());
+    }
+
+    #[test]
+    fn test_jws_with_another_algorithm_is_rejected() {
+        let token = token(RECEIPT_TYP, &receipt_payload());
+        let (header, rest) = token.split_once('.').unwrap();
+        let header = String::from_utf8(BASE64URL.decode(header).unwrap()).unwrap().replace("EdDSA", "ES256");
+        let token = format!("{}.{}", BASE64URL.encode(header), rest);
+        assert!(parse_jws(&token).unwrap_err().to_string().contains("ES256"));
+    }
+
+    fn signed(public_key: String) -> crate::Signed {
+        crate::Signed {
+            leaf_hash: "ab".repeat(32),
+            log_id: Some(3),
+            kid: None,
+            public_key,
+        }
+    }
+
+    #[test]
+    fn test_cose_receipt_verifies_through_its_inclusion_proof() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = key.verifying_key().to_bytes();
+        let leaves: Vec<Vec<u8>> = (0u8..3).map(|i| vec![i; 32]).collect();
+        let tree = tree(3, 2, &leaves[2]);
+        let path: Vec<Vec<u8>> = tree.inclusion_path(2, 3).iter().map(|hash| hash.to_vec()).collect();
+
+        let receipt = cose::sign_receipt(&key, "k1", 2, 3, &tree.root(3), &path).unwrap();
+        assert_eq!(cose_kid(&receipt).unwrap(), "k1");
+        assert!(verify_cose_receipt(&receipt, &leaves[2], &public_key).unwrap());
+        assert!(!verify_cose_receipt(&receipt, &leaves[1], &public_key).unwrap());
+
+        // A signature over another root does not cover this leaf's
+        let tampered = cose::sign_receipt(&key, "k1", 2, 3, &tree.leaf(2), &path).unwrap();
+        assert!(!verify_cose_receipt(&tampered, &leaves[2], &public_key).unwrap());
+
+        // The proof must be under vdp label -1
+        let mut moved = parse_cose(&receipt).unwrap();
+        for (_, proofs) in moved.unprotected.rest.iter_mut() {
+            if let CborValue::Map(proofs) = proofs {
+                proofs[0].0 = CborValue::Integer((-2).into());
+            }
+        }
+        let error = verify_cose_receipt(&moved.to_tagged_vec().unwrap(), &leaves[2], &public_key).unwrap_err();
+        assert!(error.to_string().contains("no inclusion proof"));
+
+        // And the log must be an RFC 9162 SHA-256 one (vds 395 = 1)
+        let mut other_log = parse_cose(&receipt).unwrap();
+        other_log.protected.original_data = None;
+        for (label, value) in other_log.protected.header.rest.iter_mut() {
+            if *label == Label::Int(COSE_VDS) {
+                *value = CborValue::Integer(2.into());
+            }
+        }
+        let error = verify_cose_receipt(&other_log.to_tagged_vec().unwrap(), &leaves[2], &public_key).unwrap_err();
+        assert!(error.to_string().contains("RFC 9162"));
+    }
+
+    #[test]
+    fn test_signed_revocation_verifies_and_tampering_does_not() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let revocation = Revocation::sign(&key, "k1", 3, &"ab".repeat(32), "leaked", "admin", "2025-05-01T00:00:00+00:00".to_string())
+            .unwrap();
+        let receipt = signed(revocation.public_key.clone());
+        let statement = serde_json::to_string(&revocation).unwrap();
+        assert_eq!(verify_revocation(&receipt, &statement, &[]).unwrap().reason, "leaked");
+
+        let tampered = statement.replace("leaked", "expired");
+        assert!(verify_revocation(&receipt, &tampered, &[]).is_err());
+        // The domain tag is signed, so it cannot be swapped either
+        let relabelled = statement.replace("verillm.revocation", "verillm.receipt");
+        assert!(verify_revocation(&receipt, &relabelled, &[]).is_err());
+        // Statements without a domain and version are not accepted at all
+        let mut unversioned: Value = serde_json::from_str(&statement).unwrap();
+        unversioned.as_object_mut().unwrap().retain(|name, _| name != "domain" && name != "version");
+        assert!(verify_revocation(&receipt, &unversioned.to_string(), &[]).is_err());
+    }
 }
@@ -1,7 +1,7 @@
 use clap::{Parser, Subcommand};
 use reqwest::Client;
 use serde_json::Value;
-use anyhow::Result;
+use anyhow::{bail, Result};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -14,11 +14,37 @@ struct Cli {
 enum Commands {
     /// Verify a receipt file
     Verify {
-        /// Path to receipt file (JSON)
+        /// Path to receipt file (JWS, or COSE as raw CBOR, hex or base64), or
+        /// to a JSON verify request
         receipt_file: String,
         /// Optional API endpoint (defaults to http://localhost:3001/verify)
         #[arg(short, long, default_value = "http://localhost:3001/verify")]
         api: String,
+        /// Hex hash the receipt is for; required for COSE receipts
+        #[arg(long)]
+        leaf_hash: Option<String>,
+        /// Base64 public key for COSE receipts, when the verifier has no
+        /// auditor to look the key up from
+        #[arg(long)]
+        public_key: Option<String>,
+    },
+    /// Fetch a receipt from the auditor's REST gateway
+    Fetch {
+        /// Hex hash of the submission
+        leaf_hash: String,
+        /// Auditor REST gateway (defaults to http://localhost:8080)
+        #[arg(long, default_value = "http://localhost:8080")]
+        auditor: String,
+        /// API key for the auditor
+        #[arg(long)]
+        api_key: Option<String>,
+        /// "jws" or "cose"
+        #[arg(long, default_value = "jws")]
+        format: String,
+        /// Write the receipt here instead of printing it; COSE receipts are
+        /// written as raw CBOR
+        #[arg(short, long)]
+        output: Option<String>,
     },
 }
 
@@ -26,17 +52,54 @@ enum Commands {
 async fn main() -> Result<()> {
     let cli = Cli::parse();
     match cli.command {
-        Commands::Verify { receipt_file, api } => {
-            let receipt_content = std::fs::read_to_string(receipt_file)?;
-            let receipt_json: Value = serde_json::from_str(&receipt_content)?;
+        Commands::Verify { receipt_file, api, leaf_hash, public_key } => {
+            let receipt_content = std::fs::read(receipt_file)?;
+            let body = match serde_json::from_slice::<Value>(&receipt_content) {
+                Ok(_) if leaf_hash.is_some() || public_key.is_some() => {
+                    bail!("--leaf-hash and --public-key apply only to COSE receipts; a JSON request carries its own");
+                }
+                Ok(receipt_json) => receipt_json,
+                Err(_) => {
+                    // JWS and encoded COSE receipts are text; raw CBOR is not
+                    let receipt = match String::from_utf8(receipt_content) {
+                        Ok(text) => text.trim().to_string(),
+                        Err(e) => hex::encode(e.into_bytes()),
+                    };
+                    serde_json::json!({
+                        "receipt": receipt,
+                        "leaf_hash": leaf_hash,
+                        "public_key": public_key,
+                    })
+                }
+            };
             let client = Client::new();
             let response = client.post(&api)
-                .json(&receipt_json)
+                .json(&body)
                 .send()
                 .await?;
             let result: Value = response.json().await?;
             println!("{}", serde_json::to_string_pretty(&result)?);
         }
+        Commands::Fetch { leaf_hash, auditor, api_key, format, output } => {
+            let client = Client::new();
+            let mut request = client
+                .get(format!("{}/v1/receipts/{}", auditor.trim_end_matches('/'), leaf_hash))
+                .query(&[("format", &format)]);
+            if let Some(api_key) = api_key {
+                request = request.header("x-api-key", api_key);
+            }
+            let response = request.send().await?;
+            if !response.status().is_success() {
+                bail!("Auditor returned {}: {}", response.status(), response.text().await?);
+            }
+            let result: Value = response.json().await?;
+            let receipt = result["receipt"].as_str().unwrap_or_default();
+            match output {
+                Some(path) if format == "cose" => std::fs::write(path, hex::decode(receipt)?)?,
+                Some(path) => std::fs::write(path, receipt)?,
+                None => println!("{}", receipt),
+            }
+        }
     }
     Ok(())
 }